@@ -0,0 +1,8 @@
+#!/bin/sh
+# Compiles every GLSL shader in this directory to SPIR-V next to its source.
+set -e
+cd "$(dirname "$0")"
+for source in *.vert *.frag *.comp; do
+    [ -e "$source" ] || continue
+    glslc "$source" -o "$source.spv"
+done
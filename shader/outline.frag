@@ -0,0 +1,19 @@
+#version 450
+
+// Set by the engine when the swapchain is UNORM, see ENCODE_SRGB_CONSTANT_ID.
+layout(constant_id = 1000) const bool ENCODE_SRGB = false;
+
+layout(location = 0) out vec4 out_color;
+
+// Selection highlight, in linear color.
+const vec3 OUTLINE_COLOR = vec3(1.0, 0.45, 0.0);
+
+vec3 linear_to_srgb(vec3 linear) {
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(linear, vec3(0.0031308)));
+}
+
+void main() {
+    out_color = vec4(ENCODE_SRGB ? linear_to_srgb(OUTLINE_COLOR) : OUTLINE_COLOR, 1.0);
+}
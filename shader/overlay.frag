@@ -0,0 +1,33 @@
+#version 450
+
+layout(location = 0) in vec2 cell_uv;
+layout(location = 1) flat in uint glyph;
+
+layout(location = 0) out vec4 out_color;
+
+// 3x5 glyphs for ASCII 32 to 95, rows from the top, leftmost pixel in the
+// highest of the 15 bits.
+const uint glyphs[64] = uint[](
+    0x0000u, 0x2482u, 0x5A00u, 0x5F7Du, 0x3C9Eu, 0x52A5u, 0x2AABu, 0x2400u,
+    0x1491u, 0x4494u, 0x0AA8u, 0x05D0u, 0x0014u, 0x01C0u, 0x0002u, 0x12A4u,
+    0x7B6Fu, 0x2C97u, 0x73E7u, 0x72CFu, 0x5BC9u, 0x79CFu, 0x79EFu, 0x7252u,
+    0x7BEFu, 0x7BCFu, 0x0410u, 0x0414u, 0x1511u, 0x0E38u, 0x4454u, 0x72C2u,
+    0x7BE7u, 0x2BEDu, 0x6BAEu, 0x3923u, 0x6B6Eu, 0x79A7u, 0x79A4u, 0x396Bu,
+    0x5BEDu, 0x7497u, 0x126Au, 0x5D35u, 0x4927u, 0x5FEDu, 0x6B6Du, 0x2B6Au,
+    0x6BA4u, 0x2B73u, 0x6BADu, 0x388Eu, 0x7492u, 0x5B6Fu, 0x5B6Au, 0x5BFDu,
+    0x5AADu, 0x5A92u, 0x72A7u, 0x3493u, 0x4889u, 0x6496u, 0x2A00u, 0x0007u
+);
+
+void main() {
+    // The glyph covers the top-left 3x5 pixels of a 4x6 cell, which leaves
+    // a one pixel gap between characters and lines.
+    uvec2 pixel = uvec2(cell_uv * vec2(4.0, 6.0));
+    if (pixel.x >= 3u || pixel.y >= 5u || glyph < 32u || glyph >= 96u) {
+        discard;
+    }
+    uint bit = 14u - (pixel.y * 3u + pixel.x);
+    if (((glyphs[glyph - 32u] >> bit) & 1u) == 0u) {
+        discard;
+    }
+    out_color = vec4(1.0, 1.0, 0.0, 1.0);
+}
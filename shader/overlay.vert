@@ -0,0 +1,30 @@
+#version 450
+
+// One quad per character of a single line of text. The text is packed as
+// four ASCII bytes per uint, first character in the lowest byte.
+layout(push_constant) uniform PushConstants {
+    vec2 origin;
+    vec2 cell_size;
+    uint text[28];
+} push;
+
+layout(location = 0) out vec2 cell_uv;
+layout(location = 1) flat out uint glyph;
+
+vec2 corners[6] = vec2[](
+    vec2(0.0, 0.0),
+    vec2(1.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0),
+    vec2(0.0, 0.0)
+);
+
+void main() {
+    uint char_index = uint(gl_VertexIndex) / 6u;
+    vec2 corner = corners[gl_VertexIndex % 6];
+    glyph = (push.text[char_index / 4u] >> (8u * (char_index % 4u))) & 0xFFu;
+    cell_uv = corner;
+    vec2 position = push.origin + (vec2(float(char_index), 0.0) + corner) * push.cell_size;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
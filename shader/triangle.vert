@@ -0,0 +1,33 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    float angle;
+    // Width over height, to keep the triangle from stretching with the window.
+    float aspect;
+    // Grows the triangle around its center, e.g. for an outline pass.
+    float scale;
+} push;
+
+layout(location = 0) out vec3 frag_color;
+
+// Counter-clockwise on screen, where Y points down, the default front face.
+vec2 positions[3] = vec2[](
+    vec2(0.0, -0.5),
+    vec2(-0.5, 0.5),
+    vec2(0.5, 0.5)
+);
+
+vec3 colors[3] = vec3[](
+    vec3(1.0, 0.0, 0.0),
+    vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 1.0, 0.0)
+);
+
+void main() {
+    float s = sin(push.angle);
+    float c = cos(push.angle);
+    vec2 position = mat2(c, s, -s, c) * positions[gl_VertexIndex] * push.scale;
+    position.x /= push.aspect;
+    gl_Position = vec4(position, 0.0, 1.0);
+    frag_color = colors[gl_VertexIndex];
+}
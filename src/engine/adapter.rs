@@ -0,0 +1,143 @@
+use std::env;
+
+use ash::{
+    vk::{
+        self, ApplicationInfo, InstanceCreateFlags, InstanceCreateInfo, MemoryHeapFlags,
+        PhysicalDevice, PhysicalDeviceProperties2, PhysicalDeviceSubgroupProperties,
+        PhysicalDeviceType, ShaderStageFlags, SubgroupFeatureFlags,
+    },
+    Entry, Instance,
+};
+
+use super::error::EngineError;
+
+/// Selects the physical device by its index in `list_adapters` instead of
+/// taking the first suitable one.
+pub const DEVICE_INDEX_VAR: &str = "MALBI_DEVICE_INDEX";
+
+/// A physical device as reported by the driver.
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    pub index: usize,
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    /// Combined size of the device local memory heaps, in bytes.
+    pub vram: u64,
+    pub capabilities: Capabilities,
+}
+
+/// Optional features and limits worth checking before relying on them in
+/// shaders or pipelines.
+#[derive(Clone, Copy, Debug)]
+pub struct Capabilities {
+    /// Zero, like the other subgroup fields, on Vulkan 1.0 devices.
+    pub subgroup_size: u32,
+    pub subgroup_operations: SubgroupFeatureFlags,
+    pub subgroup_stages: ShaderStageFlags,
+    pub geometry_shader: bool,
+    pub tessellation_shader: bool,
+    pub multi_draw_indirect: bool,
+    pub sampler_anisotropy: bool,
+    /// Lines wider than 1.0, see `MaterialDesc::line_width`.
+    pub wide_lines: bool,
+    pub max_push_constants_size: u32,
+    pub max_bound_descriptor_sets: u32,
+}
+
+/// Enumerates every physical device without creating a window, surface or
+/// logical device, so devices that could not present are listed as well.
+pub fn list_adapters() -> Result<Vec<AdapterInfo>, EngineError> {
+    unsafe {
+        let entry = Entry::load().map_err(EngineError::NoVulkanLoader)?;
+        let app_info = ApplicationInfo::default()
+            .api_version(vk::API_VERSION_1_1)
+            .application_name(c"Malbi");
+        let extensions = [vk::KHR_PORTABILITY_ENUMERATION_NAME.as_ptr()];
+        let instance = entry
+            .create_instance(
+                &InstanceCreateInfo::default()
+                    .application_info(&app_info)
+                    .enabled_extension_names(&extensions)
+                    .flags(InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR),
+                None,
+            )
+            .expect("Failed to create instance");
+
+        let adapters = instance
+            .enumerate_physical_devices()
+            .expect("Failed to enumerate physical devices")
+            .into_iter()
+            .enumerate()
+            .map(|(index, physical_device)| {
+                let properties = instance.get_physical_device_properties(physical_device);
+                AdapterInfo {
+                    index,
+                    name: properties
+                        .device_name_as_c_str()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    device_type: properties.device_type,
+                    vram: device_local_memory(&instance, physical_device),
+                    capabilities: query_capabilities(&instance, physical_device),
+                }
+            })
+            .collect();
+
+        instance.destroy_instance(None);
+        Ok(adapters)
+    }
+}
+
+/// Total size of the device local memory heaps.
+pub(super) fn device_local_memory(instance: &Instance, physical_device: PhysicalDevice) -> u64 {
+    unsafe {
+        instance
+            .get_physical_device_memory_properties(physical_device)
+            .memory_heaps_as_slice()
+            .iter()
+            .filter(|heap| heap.flags.contains(MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum()
+    }
+}
+
+pub(super) fn query_capabilities(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+) -> Capabilities {
+    unsafe {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let features = instance.get_physical_device_features(physical_device);
+        // Subgroup properties are core in Vulkan 1.1 and unknown before.
+        let mut subgroup_properties = PhysicalDeviceSubgroupProperties::default();
+        if properties.api_version >= vk::API_VERSION_1_1 {
+            instance.get_physical_device_properties2(
+                physical_device,
+                &mut PhysicalDeviceProperties2::default().push_next(&mut subgroup_properties),
+            );
+        }
+
+        Capabilities {
+            subgroup_size: subgroup_properties.subgroup_size,
+            subgroup_operations: subgroup_properties.supported_operations,
+            subgroup_stages: subgroup_properties.supported_stages,
+            geometry_shader: features.geometry_shader == vk::TRUE,
+            tessellation_shader: features.tessellation_shader == vk::TRUE,
+            multi_draw_indirect: features.multi_draw_indirect == vk::TRUE,
+            sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+            wide_lines: features.wide_lines == vk::TRUE,
+            max_push_constants_size: properties.limits.max_push_constants_size,
+            max_bound_descriptor_sets: properties.limits.max_bound_descriptor_sets,
+        }
+    }
+}
+
+/// The device index requested through `MALBI_DEVICE_INDEX`, if any.
+pub(super) fn requested_device_index() -> Option<usize> {
+    let value = env::var(DEVICE_INDEX_VAR).ok()?;
+    let index = value.parse().ok();
+    if index.is_none() {
+        println!("Ignoring {DEVICE_INDEX_VAR}={value:?}, expected a device index");
+    }
+    index
+}
@@ -1,58 +1,288 @@
-use std::process::exit;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Error, Seek},
+    path::Path,
+    time::{Duration, Instant},
+};
 
+use ash::vk::ImageUsageFlags;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+use winit::platform::{wayland::EventLoopBuilderExtWayland, x11::EventLoopBuilderExtX11};
 use winit::{
     application::ApplicationHandler,
-    dpi::Size,
-    event::WindowEvent,
-    window::{Window, WindowAttributes},
+    dpi::{PhysicalPosition, Size},
+    error::EventLoopError,
+    event::{KeyEvent, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{CursorGrabMode, Fullscreen, Icon, Window, WindowAttributes},
+};
+
+use super::{
+    base_configuration::{BaseConfig, RendererSettings},
+    benchmark::Benchmark,
+    error::EngineError,
+    game::{Game, InputState, RenderContext},
+    material::MaterialDesc,
+    overlay::DebugOverlay,
 };
 
-use super::base_configuration::BaseConfig;
+const MAX_FRAME_DELTA: Duration = Duration::from_millis(100);
+/// Minimum time between two frame budget warnings.
+const BUDGET_WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Windowing system to connect to. Only matters on Linux and the BSDs, where
+/// winit otherwise prefers Wayland and falls back to X11.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Auto,
+    X11,
+    Wayland,
+}
+
+/// Creates the event loop to run an `Application` on, forcing `backend`
+/// unless it is `Auto`. Forcing X11 runs through XWayland on Wayland
+/// sessions, which can sidestep Wayland specific surface issues.
+pub fn create_event_loop(backend: Backend) -> Result<EventLoop<()>, EventLoopError> {
+    let mut builder = EventLoop::builder();
+    match backend {
+        Backend::Auto => {}
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))]
+        Backend::X11 => {
+            builder.with_x11();
+        }
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))]
+        Backend::Wayland => {
+            builder.with_wayland();
+        }
+        #[allow(unreachable_patterns)]
+        _ => println!("The {backend:?} backend is not available on this platform, ignoring it"),
+    }
+    builder.build()
+}
+
 pub struct Application {
     pub base_config: Option<BaseConfig>,
+    game: Box<dyn Game>,
+    input: InputState,
+    overlay: DebugOverlay,
     resolution: Size,
+    /// `Poll` redraws continuously; `Wait` only redraws after window events
+    /// or while the game asks for more frames.
+    control_flow: ControlFlow,
+    max_fps: Option<u32>,
+    renderer_settings: RendererSettings,
+    /// Stops the event loop with an error on the first frame that produced
+    /// validation errors.
+    strict_validation: bool,
+    /// Registered by name before `Game::init` runs.
+    startup_materials: Vec<(String, MaterialDesc)>,
+    title: String,
+    icon: Option<Icon>,
     window: Option<Window>,
+    fullscreen: bool,
+    /// How the cursor is currently held, `None` while it moves freely.
+    cursor_grab: Option<CursorGrabMode>,
+    last_frame: Instant,
+    last_title_update: Instant,
+    /// CPU or GPU frame time above which a warning is logged.
+    frame_budget_ms: Option<f32>,
+    last_budget_warning: Option<Instant>,
+    /// Set in benchmark mode, which renders a fixed number of frames to a
+    /// hidden window and exits.
+    benchmark: Option<Benchmark>,
+    /// Why the event loop was stopped, if it wasn't closed normally.
+    exit_error: Option<EngineError>,
 }
 
 impl ApplicationHandler for Application {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         println!("{:?}", self.resolution);
 
-        self.window = Some(
-            event_loop
-                .create_window(
-                    WindowAttributes::default()
-                        .with_max_inner_size(self.resolution)
-                        .with_active(true),
-                )
-                .expect("Failed to create window"),
-        );
-        println!("window created");
+        // A window kept across a suspension is reused; only its surface had
+        // to go.
+        if self.window.is_none() {
+            self.window = Some(
+                event_loop
+                    .create_window(
+                        WindowAttributes::default()
+                            .with_title(&self.title)
+                            .with_window_icon(self.icon.clone())
+                            .with_max_inner_size(self.resolution)
+                            .with_visible(self.benchmark.is_none())
+                            .with_active(true),
+                    )
+                    .expect("Failed to create window"),
+            );
+            println!("window created");
+        }
 
-        let base_config_res = BaseConfig::init(self.window.as_mut().unwrap());
+        let base_config_res = BaseConfig::init(
+            self.window.as_mut().unwrap(),
+            self.renderer_settings.clone(),
+        );
         match base_config_res {
-            Ok(base) => {
+            Ok(mut base) => {
+                for (name, desc) in &self.startup_materials {
+                    if let Err(err) = base.register_named_material(name, desc.clone()) {
+                        println!("Failed to load material {name:?}: {err}");
+                    }
+                }
+                self.game.init(&mut RenderContext::new(&mut base));
+                self.overlay.init(&mut base);
+                base.set_max_fps(self.max_fps);
                 self.base_config = Some(base);
+                self.last_frame = Instant::now();
+            }
+            Err(err) => {
+                println!("Failed to initialize the renderer: {err}");
+                self.exit_error = Some(err);
+                event_loop.exit();
             }
-            Err(_) => panic!(),
         }
     }
 
+    /// The surface may be invalid until the next `resumed`, e.g. on Android,
+    /// so the renderer is torn down here and rebuilt from scratch there,
+    /// calling `Game::init` again. Materials have to be registered anew.
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        if self.base_config.take().is_some() {
+            println!("Suspended, renderer released");
+        }
+    }
+
+    /// Covers the exits that did not go through `shutdown` already, e.g. on
+    /// errors.
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        self.shutdown();
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        event_loop.set_control_flow(self.control_flow);
+    }
+
     fn window_event(
         &mut self,
         event_loop: &winit::event_loop::ActiveEventLoop,
-        window_id: winit::window::WindowId,
+        _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
+        // Under `Wait` nothing else schedules frames, so show the effect of
+        // input and resizes right away.
+        if matches!(
+            event,
+            WindowEvent::KeyboardInput { .. }
+                | WindowEvent::Resized(_)
+                | WindowEvent::ScaleFactorChanged { .. }
+        ) {
+            self.request_redraw();
+        }
+
         match event {
             WindowEvent::Destroyed => {
-                let _x = self.base_config.as_mut().unwrap();
-                drop(_x);
+                // The surface must not outlive its window.
+                self.shutdown();
+                event_loop.exit();
             }
             WindowEvent::CloseRequested => {
+                self.shutdown();
                 event_loop.exit();
             }
-            WindowEvent::RedrawRequested => {}
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key_code),
+                        state,
+                        repeat,
+                        ..
+                    },
+                ..
+            } => {
+                self.input.set_pressed(key_code, state.is_pressed());
+                if state.is_pressed() && !repeat {
+                    self.handle_key(key_code);
+                }
+            }
+            WindowEvent::Resized(size) => self.on_resize(size.width, size.height),
+            // The swapchain always follows the physical size. Not every
+            // platform follows a scale change with `Resized`, so read it back.
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                println!("Scale factor: {scale_factor}");
+                if let Some(size) = self.window.as_ref().map(Window::inner_size) {
+                    self.on_resize(size.width, size.height);
+                }
+            }
+            // Don't keep the cursor captured while another window has focus.
+            WindowEvent::Focused(false) => self.set_cursor_grab(false),
+            WindowEvent::RedrawRequested => {
+                let now = Instant::now();
+                // Frames can be far apart under `Wait`; don't let an idle
+                // stretch turn into one huge simulation step.
+                let dt = now
+                    .duration_since(self.last_frame)
+                    .min(MAX_FRAME_DELTA)
+                    .as_secs_f32();
+                self.last_frame = now;
+
+                let cpu_start = Instant::now();
+                self.game.update(dt, &self.input);
+                if let Some(base_config) = self.base_config.as_mut() {
+                    self.game.render(&mut RenderContext::new(base_config));
+                    let cpu_frame_ms = cpu_start.elapsed().as_secs_f32() * 1000.0;
+                    let scale_factor = self.window.as_ref().map_or(1.0, Window::scale_factor);
+                    self.overlay
+                        .draw(base_config, cpu_frame_ms, scale_factor as f32);
+                    let mut result = base_config.draw_frame();
+                    if self.strict_validation && result.is_ok() {
+                        let errors = base_config.take_validation_errors();
+                        if !errors.is_empty() {
+                            result = Err(EngineError::ValidationErrors(errors));
+                        }
+                    }
+                    if let Err(err) = result {
+                        println!("{err}, exiting");
+                        self.exit_error = Some(err);
+                        event_loop.exit();
+                        return;
+                    }
+                    let gpu_frame_ms = base_config.last_gpu_frame_ms();
+                    let record_ms = base_config.last_record_ms();
+                    self.check_frame_budget(cpu_frame_ms);
+                    if let Some(benchmark) = self.benchmark.as_mut() {
+                        benchmark.record(cpu_frame_ms, gpu_frame_ms, record_ms);
+                        if benchmark.is_done() {
+                            println!("{}", benchmark.report());
+                            self.shutdown();
+                            event_loop.exit();
+                            return;
+                        }
+                    }
+                }
+                self.update_title();
+                self.recenter_cursor();
+                if self.control_flow == ControlFlow::Poll || self.game.wants_redraw() {
+                    self.request_redraw();
+                }
+            }
             _ => {
                 println!("{:?}", event);
             }
@@ -61,14 +291,449 @@ impl ApplicationHandler for Application {
 }
 
 impl Application {
-    pub fn new<S>(resolution: S) -> Self
+    pub fn new<S>(resolution: S, game: Box<dyn Game>) -> Self
     where
         S: Into<Size>,
     {
         Self {
             base_config: None,
+            game,
+            input: InputState::default(),
+            overlay: DebugOverlay::new(),
             resolution: resolution.into(),
+            control_flow: ControlFlow::Poll,
+            max_fps: None,
+            renderer_settings: RendererSettings::default(),
+            strict_validation: false,
+            startup_materials: Vec::new(),
+            title: "Malbi".to_owned(),
+            icon: None,
             window: None,
+            fullscreen: false,
+            cursor_grab: None,
+            last_frame: Instant::now(),
+            last_title_update: Instant::now(),
+            frame_budget_ms: None,
+            last_budget_warning: None,
+            benchmark: None,
+            exit_error: None,
         }
     }
+
+    /// Selects how the event loop waits between frames. Defaults to `Poll`.
+    pub fn with_control_flow(mut self, control_flow: ControlFlow) -> Self {
+        self.control_flow = control_flow;
+        self
+    }
+
+    /// Caps the frame rate, regardless of the present mode. Uncapped by default.
+    pub fn with_max_fps(mut self, max_fps: Option<u32>) -> Self {
+        self.max_fps = max_fps;
+        self
+    }
+
+    /// Loads a named material when the renderer starts, to be looked up with
+    /// `RenderContext::material`. Materials whose shaders fail to load are
+    /// reported and left out.
+    pub fn with_material(mut self, name: impl Into<String>, desc: MaterialDesc) -> Self {
+        self.startup_materials.push((name.into(), desc));
+        self
+    }
+
+    /// Logs a warning, at most once a second, whenever the CPU time spent in
+    /// `Game::update` and `Game::render` or the GPU time of a frame exceeds
+    /// `budget_ms`. Off by default.
+    pub fn with_frame_budget_ms(mut self, budget_ms: Option<f32>) -> Self {
+        self.frame_budget_ms = budget_ms;
+        self
+    }
+
+    /// Renders `frames` frames to a hidden window as fast as possible, prints
+    /// their CPU, GPU and command buffer recording times as JSON and exits.
+    /// Forces `Poll`. Wayland has no hidden windows, so the window shows
+    /// there.
+    pub fn with_benchmark(mut self, frames: usize) -> Self {
+        self.benchmark = Some(Benchmark::new(frames));
+        self.control_flow = ControlFlow::Poll;
+        self
+    }
+
+    /// Sets the window title. The GPU frame time is appended to it while
+    /// running. Defaults to "Malbi".
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Sets the window icon from a PNG file. A missing or invalid file is
+    /// reported and the default icon kept.
+    pub fn with_icon_file(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match File::open(path).and_then(|file| decode_png_rgba(BufReader::new(file))) {
+            Ok((rgba, width, height)) => self.with_icon(rgba, width, height),
+            Err(err) => {
+                println!("Ignoring window icon {}: {err}", path.display());
+                self
+            }
+        }
+    }
+
+    /// Sets the window icon from 8-bit RGBA pixels, row by row. Invalid data
+    /// is reported and the default icon kept.
+    pub fn with_icon(mut self, rgba: Vec<u8>, width: u32, height: u32) -> Self {
+        self.icon = Icon::from_rgba(rgba, width, height)
+            .inspect_err(|err| println!("Ignoring invalid window icon: {err}"))
+            .ok();
+        self
+    }
+
+    /// Requests HDR10 output, used when the display supports it. Off by default.
+    pub fn with_hdr(mut self, hdr: bool) -> Self {
+        self.renderer_settings.hdr = hdr;
+        self
+    }
+
+    /// Records frames with at least `min_draws` draws on worker threads, or
+    /// every frame on the main thread with `None`. Defaults to 1024 draws.
+    pub fn with_parallel_recording_min_draws(mut self, min_draws: Option<usize>) -> Self {
+        self.renderer_settings.parallel_recording_min_draws = min_draws;
+        self
+    }
+
+    /// Presents to a UNORM instead of an SRGB swapchain, leaving the sRGB
+    /// encoding to the shaders. Off by default.
+    pub fn with_unorm_swapchain(mut self, unorm_swapchain: bool) -> Self {
+        self.renderer_settings.unorm_swapchain = unorm_swapchain;
+        self
+    }
+
+    /// Sets the usage of the swapchain images, e.g. adding `STORAGE` to
+    /// write them from compute shaders. `COLOR_ATTACHMENT` is always
+    /// included. Defaults to `COLOR_ATTACHMENT | TRANSFER_SRC`.
+    pub fn with_swapchain_usage(mut self, usage: ImageUsageFlags) -> Self {
+        self.renderer_settings.swapchain_usage = usage;
+        self
+    }
+
+    /// Sets how many frames may be in flight, from 1 (lowest latency) to 3.
+    /// Defaults to 2.
+    pub fn with_frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.renderer_settings.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    /// Treats validation errors as fatal, for running under CI: the event
+    /// loop stops after the first frame that produced any, and
+    /// `exit_error` holds them. Off by default.
+    pub fn with_strict_validation(mut self, strict_validation: bool) -> Self {
+        self.strict_validation = strict_validation;
+        self
+    }
+
+    /// Leaves validation messages with this `message_id_name` out of the log,
+    /// e.g. a known false positive.
+    pub fn with_ignored_debug_message(mut self, message_id_name: impl Into<String>) -> Self {
+        self.renderer_settings
+            .debug
+            .ignored_message_names
+            .push(message_id_name.into());
+        self
+    }
+
+    /// Like `with_ignored_debug_message`, matching `message_id_number`, for
+    /// messages without a name.
+    pub fn with_ignored_debug_message_id(mut self, message_id_number: i32) -> Self {
+        self.renderer_settings
+            .debug
+            .ignored_message_ids
+            .push(message_id_number);
+        self
+    }
+
+    /// Makes clip space Y point up, as in OpenGL. Off by default.
+    pub fn with_flipped_viewport(mut self, flip_viewport_y: bool) -> Self {
+        self.renderer_settings.flip_viewport_y = flip_viewport_y;
+        self
+    }
+
+    /// Switches the control flow at runtime. Takes effect once the current
+    /// batch of events has been handled.
+    pub fn set_control_flow(&mut self, control_flow: ControlFlow) {
+        self.control_flow = control_flow;
+        self.request_redraw();
+    }
+
+    /// Single place for everything that depends on the window size: the
+    /// swapchain is rebuilt before the next frame and the game gets to update
+    /// its projection and size-dependent resources.
+    pub fn on_resize(&mut self, width: u32, height: u32) {
+        if let Some(base_config) = self.base_config.as_mut() {
+            base_config.resize(width, height);
+        }
+        self.game.on_resize(width, height);
+    }
+
+    /// The error that ended the event loop, if any. The process should exit
+    /// with a failure status then.
+    pub fn exit_error(&self) -> Option<&EngineError> {
+        self.exit_error.as_ref()
+    }
+
+    fn request_redraw(&self) {
+        if let Some(window) = self.window.as_ref() {
+            window.request_redraw();
+        }
+    }
+
+    fn handle_key(&mut self, key_code: KeyCode) {
+        match key_code {
+            KeyCode::KeyP => {
+                let control_flow = if self.control_flow == ControlFlow::Poll {
+                    ControlFlow::Wait
+                } else {
+                    ControlFlow::Poll
+                };
+                println!("Control flow: {control_flow:?}");
+                self.set_control_flow(control_flow);
+            }
+            KeyCode::KeyG => self.set_cursor_grab(self.cursor_grab.is_none()),
+            KeyCode::KeyM => self.print_memory_budget(),
+            KeyCode::KeyV => self.cycle_present_mode(),
+            KeyCode::F3 => self.overlay.toggle(),
+            KeyCode::F11 => self.toggle_fullscreen(),
+            _ => {}
+        }
+    }
+
+    /// Captures and hides the cursor for mouse look, or releases it. Uses
+    /// `Locked` where supported and falls back to `Confined`, in which case
+    /// the cursor is moved back to the window center after every frame.
+    pub fn set_cursor_grab(&mut self, grab: bool) {
+        let Some(window) = self.window.as_ref() else {
+            return;
+        };
+        if !grab {
+            if self.cursor_grab.take().is_some() {
+                if let Err(err) = window.set_cursor_grab(CursorGrabMode::None) {
+                    println!("Failed to release the cursor: {err}");
+                }
+                window.set_cursor_visible(true);
+            }
+            return;
+        }
+
+        self.cursor_grab = [CursorGrabMode::Locked, CursorGrabMode::Confined]
+            .into_iter()
+            .find(|&mode| window.set_cursor_grab(mode).is_ok());
+        match self.cursor_grab {
+            Some(mode) => {
+                println!("Cursor grab: {mode:?}");
+                window.set_cursor_visible(false);
+            }
+            None => println!("Cursor grabbing is not supported on this platform"),
+        }
+    }
+
+    /// Keeps a confined cursor from reaching the window edge, where it would
+    /// stop producing motion.
+    fn recenter_cursor(&self) {
+        let (Some(window), Some(CursorGrabMode::Confined)) =
+            (self.window.as_ref(), self.cursor_grab)
+        else {
+            return;
+        };
+        let size = window.inner_size();
+        let center = PhysicalPosition::new(size.width / 2, size.height / 2);
+        // Where positioning is unsupported the cursor just stays confined;
+        // reporting that on every frame would only flood the log.
+        let _ = window.set_cursor_position(center);
+    }
+
+    /// Switches between windowed and borderless fullscreen. The resulting
+    /// `Resized` event rebuilds the swapchain at the new size.
+    fn toggle_fullscreen(&mut self) {
+        let Some(window) = self.window.as_ref() else {
+            return;
+        };
+        self.fullscreen = !self.fullscreen;
+        if self.fullscreen {
+            // The windowed size cap would otherwise keep the surface smaller than the monitor.
+            window.set_max_inner_size(None::<Size>);
+            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+        } else {
+            window.set_fullscreen(None);
+            window.set_max_inner_size(Some(self.resolution));
+        }
+    }
+
+    fn cycle_present_mode(&mut self) {
+        let Some(base_config) = self.base_config.as_mut() else {
+            return;
+        };
+        match base_config.cycle_present_mode() {
+            Some(present_mode) => {
+                println!("Present mode: {present_mode:?}");
+                self.request_redraw();
+            }
+            None => println!(
+                "Present mode: {:?} is the only one supported",
+                base_config.present_mode()
+            ),
+        }
+    }
+
+    fn print_memory_budget(&self) {
+        let Some(base_config) = self.base_config.as_ref() else {
+            return;
+        };
+        let heaps = base_config.memory_budget();
+        if heaps.is_empty() {
+            println!("VK_EXT_memory_budget is not supported on this device");
+        }
+        for heap in heaps {
+            println!(
+                "heap {}{}: {} / {} MiB",
+                heap.heap_index,
+                if heap.device_local {
+                    " (device local)"
+                } else {
+                    ""
+                },
+                heap.usage / (1024 * 1024),
+                heap.budget / (1024 * 1024),
+            );
+        }
+    }
+
+    /// Warns when the game's update and render, or the GPU work of the last
+    /// completed frame, took longer than the frame budget. Warnings are
+    /// throttled to one per `BUDGET_WARNING_INTERVAL`.
+    fn check_frame_budget(&mut self, cpu_frame_ms: f32) {
+        let (Some(budget_ms), Some(base_config)) = (self.frame_budget_ms, &self.base_config) else {
+            return;
+        };
+        if self
+            .last_budget_warning
+            .is_some_and(|warning| warning.elapsed() < BUDGET_WARNING_INTERVAL)
+        {
+            return;
+        }
+
+        let gpu_frame_ms = base_config.last_gpu_frame_ms();
+        let mut over_budget = Vec::new();
+        if cpu_frame_ms > budget_ms {
+            over_budget.push(format!("CPU {cpu_frame_ms:.2} ms"));
+        }
+        if let Some(gpu_frame_ms) = gpu_frame_ms.filter(|&gpu_frame_ms| gpu_frame_ms > budget_ms) {
+            over_budget.push(format!("GPU {gpu_frame_ms:.2} ms"));
+        }
+        if !over_budget.is_empty() {
+            println!(
+                "Warning: frame over its {budget_ms:.2} ms budget: {}",
+                over_budget.join(", ")
+            );
+            self.last_budget_warning = Some(Instant::now());
+        }
+    }
+
+    /// Waits for the GPU to finish and releases the renderer. Safe to call
+    /// more than once; later calls do nothing.
+    pub fn shutdown(&mut self) {
+        if let Some(base_config) = self.base_config.take() {
+            // Dropping waits for the device to become idle.
+            drop(base_config);
+            println!("Renderer shut down");
+        }
+    }
+
+    /// Shows the latest GPU frame time in the title bar, refreshed once a
+    /// second so the title isn't rewritten every frame.
+    fn update_title(&mut self) {
+        if self.last_title_update.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        self.last_title_update = Instant::now();
+
+        let (Some(window), Some(base_config)) = (self.window.as_ref(), self.base_config.as_ref())
+        else {
+            return;
+        };
+        if let Some(gpu_frame_ms) = base_config.last_gpu_frame_ms() {
+            window.set_title(&format!("{} - GPU {gpu_frame_ms:.2} ms", self.title));
+        }
+    }
+}
+
+/// Decodes a PNG of any color type and bit depth into 8-bit RGBA pixels, row
+/// by row, and returns them with the width and height.
+fn decode_png_rgba(reader: impl BufRead + Seek) -> Result<(Vec<u8>, u32, u32), Error> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size().unwrap_or_default()];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+    // Palettes are expanded already, and everything is 8 bits deep.
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|gray_alpha| {
+                let [gray, alpha] = [gray_alpha[0], gray_alpha[1]];
+                [gray, gray, gray, alpha]
+            })
+            .collect(),
+        png::ColorType::Grayscale | png::ColorType::Indexed => pixels
+            .iter()
+            .flat_map(|&gray| [gray, gray, gray, u8::MAX])
+            .collect(),
+    };
+    Ok((rgba, info.width, info.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn encode_png(width: u32, height: u32, color_type: png::ColorType, data: &[u8]) -> Vec<u8> {
+        let mut png_data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png_data, width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        png_data
+    }
+
+    #[test]
+    fn decodes_rgba_as_is() {
+        let pixels = [255, 0, 0, 255, 0, 255, 0, 128];
+        let png_data = encode_png(2, 1, png::ColorType::Rgba, &pixels);
+        let (rgba, width, height) = decode_png_rgba(Cursor::new(png_data)).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(rgba, pixels);
+    }
+
+    #[test]
+    fn expands_rgb_and_grayscale_to_opaque_rgba() {
+        let png_data = encode_png(1, 2, png::ColorType::Rgb, &[1, 2, 3, 4, 5, 6]);
+        let (rgba, _, _) = decode_png_rgba(Cursor::new(png_data)).unwrap();
+        assert_eq!(rgba, [1, 2, 3, 255, 4, 5, 6, 255]);
+
+        let png_data = encode_png(2, 1, png::ColorType::GrayscaleAlpha, &[7, 8, 9, 10]);
+        let (rgba, _, _) = decode_png_rgba(Cursor::new(png_data)).unwrap();
+        assert_eq!(rgba, [7, 7, 7, 8, 9, 9, 9, 10]);
+    }
+
+    #[test]
+    fn rejects_data_that_is_not_a_png() {
+        assert!(decode_png_rgba(Cursor::new(b"not a png".to_vec())).is_err());
+    }
 }
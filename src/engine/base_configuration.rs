@@ -1,17 +1,56 @@
 use core::ffi;
 use std::{
     borrow::Cow,
+    cmp::Reverse,
+    collections::HashMap,
+    fs,
     io::{Error, ErrorKind},
+    ops::RangeInclusive,
+    ptr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
 };
 
 use ash::{
-    ext::debug_utils,
-    khr::{surface, swapchain},
+    ext::{
+        conditional_rendering, debug_utils, descriptor_indexing, device_fault, hdr_metadata,
+        memory_budget, swapchain_colorspace,
+    },
+    khr::{maintenance3, surface, swapchain, timeline_semaphore},
+    prelude::VkResult,
     vk::{
-        self, ApplicationInfo, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT,
-        DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT, DeviceCreateInfo,
-        DeviceQueueCreateInfo, InstanceCreateFlags, InstanceCreateInfo, PhysicalDevice,
-        PhysicalDeviceType, QueueFlags,
+        self, ApplicationInfo, AttachmentDescription, AttachmentLoadOp, AttachmentReference,
+        AttachmentStoreOp, BufferCopy, BufferImageCopy, BufferUsageFlags, ClearColorValue,
+        ClearDepthStencilValue, ClearValue, ColorSpaceKHR, CommandBuffer,
+        CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel,
+        CommandBufferResetFlags, CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags,
+        CommandPoolCreateInfo, ComponentMapping, CompositeAlphaFlagsKHR,
+        ConditionalRenderingBeginInfoEXT, DebugUtilsLabelEXT, DebugUtilsMessageSeverityFlagsEXT,
+        DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT,
+        DescriptorBufferInfo, DescriptorSet, DescriptorSetLayout, DescriptorSetLayoutBinding,
+        DescriptorSetLayoutCreateInfo, DescriptorType, DeviceCreateInfo, DeviceFaultAddressInfoEXT,
+        DeviceFaultCountsEXT, DeviceFaultInfoEXT, DeviceFaultVendorInfoEXT, DeviceQueueCreateInfo,
+        DeviceSize, Extent2D, Extent3D, Fence, FenceCreateFlags, FenceCreateInfo, Filter, Format,
+        FormatFeatureFlags, Framebuffer, FramebufferCreateInfo, HdrMetadataEXT, Image,
+        ImageAspectFlags, ImageBlit, ImageLayout, ImageSubresourceLayers, ImageSubresourceRange,
+        ImageUsageFlags, ImageView, ImageViewCreateInfo, ImageViewType, InstanceCreateFlags,
+        InstanceCreateInfo, MappedMemoryRange, MemoryHeapFlags, MemoryMapFlags,
+        MemoryPropertyFlags, Offset2D, Offset3D, PhysicalDevice,
+        PhysicalDeviceConditionalRenderingFeaturesEXT, PhysicalDeviceDescriptorIndexingFeatures,
+        PhysicalDeviceFaultFeaturesEXT, PhysicalDeviceFeatures2,
+        PhysicalDeviceMemoryBudgetPropertiesEXT, PhysicalDeviceMemoryProperties2,
+        PhysicalDeviceProperties, PhysicalDeviceTimelineSemaphoreFeatures, PhysicalDeviceType,
+        PipelineBindPoint, PipelineCache, PipelineCacheCreateInfo, PipelineCacheHeaderVersion,
+        PipelineLayout, PipelineLayoutCreateInfo, PipelineStageFlags, PresentInfoKHR,
+        PresentModeKHR, PrimitiveTopology, PushConstantRange, QueryPool, QueryPoolCreateInfo,
+        QueryResultFlags, QueryType, Queue, QueueFlags, Rect2D, RenderPass, RenderPassBeginInfo,
+        RenderPassCreateInfo, SampleCountFlags, Sampler, SamplerAddressMode, Semaphore,
+        SemaphoreCreateInfo, SemaphoreType, SemaphoreTypeCreateInfo, SemaphoreWaitInfo,
+        ShaderStageFlags, SharingMode, SubmitInfo, SubpassContents, SubpassDependency,
+        SubpassDescription, SurfaceCapabilitiesKHR, SurfaceFormatKHR, SurfaceKHR,
+        SwapchainCreateInfoKHR, SwapchainKHR, TimelineSemaphoreSubmitInfo, Viewport,
+        WriteDescriptorSet, XYColorEXT, SUBPASS_EXTERNAL,
     },
     Device, Entry, Instance,
 };
@@ -20,18 +59,613 @@ use winit::{
     window::Window,
 };
 
-use super::app;
+use super::{
+    adapter::{
+        device_local_memory, query_capabilities, requested_device_index, Capabilities,
+        DEVICE_INDEX_VAR,
+    },
+    bindless::BindlessTextures,
+    buffer_pool::{BufferPool, PooledBuffer},
+    deletion_queue::DeletionQueue,
+    descriptor::{DescriptorAllocator, PoolSizeRatio},
+    device_context::DeviceContext,
+    error::EngineError,
+    frame_timer::FrameTimer,
+    material::{
+        clamp_line_width, create_graphics_pipeline, supported_line_width, DepthBias, Material,
+        MaterialDesc, MaterialId, SpecConstant, ENCODE_SRGB_CONSTANT_ID,
+    },
+    parallel::ParallelRecorder,
+    render_target::RenderTarget,
+    resource::{
+        mip_level_count, Buffer, DynamicUniformBuffer, Image as ResourceImage, MappedBuffer,
+        PerFrameBuffer, PipelineGuard, SamplerDesc, SamplerGuard,
+    },
+    sync::{ImageBarrier, MemoryBarrier},
+};
+
+/// Bounds for `RendererSettings::frames_in_flight`.
+const FRAMES_IN_FLIGHT_RANGE: RangeInclusive<usize> = 1..=3;
+const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+const FRAME_DESCRIPTOR_SETS: u32 = 64;
+/// Every device guarantees at least 128 bytes of push constants.
+const PUSH_CONSTANT_SIZE: usize = 128;
+/// Default for `RendererSettings::parallel_recording_min_draws`.
+const PARALLEL_RECORDING_MIN_DRAWS: usize = 1024;
+/// Set the dynamic uniform buffer of `draw_with_uniforms` is bound to, after
+/// the bindless texture set.
+const UNIFORM_SET: u32 = 1;
+const PUSH_CONSTANT_STAGES: ShaderStageFlags = ShaderStageFlags::from_raw(
+    ShaderStageFlags::VERTEX.as_raw() | ShaderStageFlags::FRAGMENT.as_raw(),
+);
+const FRAME_DESCRIPTOR_POOL_RATIOS: [PoolSizeRatio; 3] = [
+    PoolSizeRatio {
+        descriptor_type: DescriptorType::UNIFORM_BUFFER,
+        ratio: 1.0,
+    },
+    PoolSizeRatio {
+        descriptor_type: DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+        ratio: 1.0,
+    },
+    PoolSizeRatio {
+        descriptor_type: DescriptorType::COMBINED_IMAGE_SAMPLER,
+        ratio: 1.0,
+    },
+];
+/// Depth/stencil formats in order of preference. No single one is supported
+/// everywhere.
+const DEPTH_STENCIL_FORMATS: [Format; 3] = [
+    Format::D24_UNORM_S8_UINT,
+    Format::D32_SFLOAT_S8_UINT,
+    Format::D16_UNORM_S8_UINT,
+];
+const PIPELINE_CACHE_PATH: &str = "pipeline_cache.bin";
+/// Size of `VkPipelineCacheHeaderVersionOne`: length, version, vendor id,
+/// device id and the 16 byte cache UUID.
+const PIPELINE_CACHE_HEADER_SIZE: usize = 32;
 
 pub struct BaseConfig {
-    instance: Instance,
+    context: Arc<DeviceContext>,
+    debug_instance: debug_utils::Instance,
+    debug_utils_messenger: Option<DebugUtilsMessengerEXT>,
+    debug_utils_device: Option<debug_utils::Device>,
+    surface_instance: surface::Instance,
+    surface: SurfaceKHR,
+    memory_budget_supported: bool,
+    device_fault: Option<device_fault::Device>,
+    conditional_rendering: Option<conditional_rendering::Device>,
+    /// Set 0 of the pipeline layout, when descriptor indexing is supported.
+    bindless_textures: Option<BindlessTextures>,
+    pipeline_cache: PipelineCache,
+    graphics_queue: Queue,
+    presentation_queue: Option<Queue>,
+    queue_family_indices: QueueFamilyIndices,
+    swapchain_device: swapchain::Device,
+    swapchain: SwapchainKHR,
+    swapchain_settings: SwapchainSettings,
+    hdr_metadata: Option<hdr_metadata::Device>,
+    swapchain_image_views: Vec<ImageView>,
+    swapchain_extent: Extent2D,
+    window_extent: Extent2D,
+    swapchain_outdated: bool,
+    render_pass: RenderPass,
+    /// Format of the swapchain sized stencil attachment of `render_pass`,
+    /// if the device has one.
+    depth_stencil_format: Option<Format>,
+    depth_stencil_image: Option<ResourceImage>,
+    framebuffers: Vec<Framebuffer>,
+    pipeline_layout: PipelineLayout,
+    /// Stands in for the bindless texture set when that is unsupported.
+    empty_set_layout: Option<DescriptorSetLayout>,
+    /// Set 1: one dynamic uniform buffer, see `draw_with_uniforms`.
+    uniform_set_layout: DescriptorSetLayout,
+    materials: Vec<Material>,
+    material_names: HashMap<String, MaterialId>,
+    pipelines: HashMap<MaterialId, PipelineGuard>,
+    pending_draws: Vec<DrawCall>,
+    clear_color: [f32; 4],
+    command_pool: CommandPool,
+    command_buffers: Vec<CommandBuffer>,
+    parallel_recorder: ParallelRecorder,
+    parallel_recording_min_draws: Option<usize>,
+    /// One per frame in flight, since the image index is only known once
+    /// the acquire has been issued.
+    image_available_semaphores: Vec<Semaphore>,
+    /// One per swapchain image, indexed by the acquired image. A semaphore
+    /// per frame could still be waited on by the presentation of an image
+    /// when the next frame signals it again.
+    render_finished_semaphores: Vec<Semaphore>,
+    /// Per-frame fences, only used when `frame_timeline` is unavailable.
+    in_flight_fences: Vec<Fence>,
+    frame_timeline: Option<FrameTimeline>,
+    frames_submitted: u64,
+    frame_timer: FrameTimer,
+    deletion_queue: DeletionQueue,
+    frame_descriptor_allocators: Vec<DescriptorAllocator>,
+    /// Frames the CPU may record ahead of the GPU. Sizes everything that is
+    /// per frame: command buffers, acquire semaphores, fences, descriptor
+    /// allocators and timestamp queries. Not to be confused with the number
+    /// of swapchain images, which the driver picks and which only bounds how
+    /// many images can be queued for presentation.
+    frames_in_flight: usize,
+    flip_viewport_y: bool,
+    current_frame: usize,
+    timestamp_query_pool: Option<QueryPool>,
+    timestamp_period: f32,
+    timestamp_mask: u64,
+    timestamps_written: Vec<bool>,
+    last_gpu_frame_ms: Option<f32>,
+    last_record_ms: Option<f32>,
+}
+
+/// A non-indexed draw recorded with the pipeline of `material`.
+#[derive(Clone, Debug)]
+struct DrawCall {
+    material: MaterialId,
+    vertex_count: u32,
+    push_constants: Vec<u8>,
+    /// Sort key for transparent materials, ignored for opaque ones.
+    camera_distance: f32,
+    /// Buffer and offset of a 32-bit value; the draw is skipped on the GPU
+    /// when it is zero.
+    predicate: Option<(vk::Buffer, DeviceSize)>,
+    /// Set for materials with dynamic depth bias only.
+    depth_bias: Option<DepthBias>,
+    /// Set for materials with dynamic line width only.
+    line_width: Option<f32>,
+    /// Element of a dynamic uniform buffer bound to `UNIFORM_SET`.
+    uniforms: Option<DrawUniforms>,
+}
+
+/// The uniform buffer element a draw reads, see `draw_with_uniforms`.
+#[derive(Clone, Copy, Debug)]
+struct DrawUniforms {
+    buffer: vk::Buffer,
+    /// Size of one element, which the descriptor covers.
+    range: DeviceSize,
+    dynamic_offset: u32,
+    /// Allocated once the frame's descriptor allocator has been reset, and
+    /// shared by every draw reading the same buffer.
+    set: DescriptorSet,
+}
+
+/// Paces frames in flight with a single timeline semaphore: the n-th
+/// submission signals value n, so a frame slot is free again once the value
+/// of the submission that last used it has been reached.
+struct FrameTimeline {
+    timeline_semaphore_device: timeline_semaphore::Device,
+    semaphore: Semaphore,
+}
+
+impl FrameTimeline {
+    fn new(instance: &Instance, device: &Device) -> Self {
+        let mut semaphore_type_info = SemaphoreTypeCreateInfo::default()
+            .semaphore_type(SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore = unsafe {
+            device
+                .create_semaphore(
+                    &SemaphoreCreateInfo::default().push_next(&mut semaphore_type_info),
+                    None,
+                )
+                .expect("Failed to create timeline semaphore")
+        };
+        Self {
+            timeline_semaphore_device: timeline_semaphore::Device::new(instance, device),
+            semaphore,
+        }
+    }
+
+    /// Blocks until the first `frames` submissions have completed.
+    fn wait_for_frames(&self, frames: u64) -> VkResult<()> {
+        let semaphores = [self.semaphore];
+        let values = [frames];
+        unsafe {
+            self.timeline_semaphore_device.wait_semaphores(
+                &SemaphoreWaitInfo::default()
+                    .semaphores(&semaphores)
+                    .values(&values),
+                u64::MAX,
+            )
+        }
+    }
+}
+
+/// Queue families picked for a physical device. Graphics and presentation
+/// may or may not live in the same family.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct QueueFamilyIndices {
+    graphics_family: u32,
+    presentation_family: u32,
+}
+
+impl QueueFamilyIndices {
+    fn is_shared(&self) -> bool {
+        self.graphics_family == self.presentation_family
+    }
+}
+
+/// Budget and current usage of a single memory heap, in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapBudget {
+    pub heap_index: u32,
+    pub device_local: bool,
+    pub budget: u64,
+    pub usage: u64,
+}
+
+/// Validation and driver messages to leave out of the log, matched by
+/// `message_id_name` or `message_id_number`.
+#[derive(Clone, Debug, Default)]
+pub struct DebugConfig {
+    pub ignored_message_names: Vec<String>,
+    pub ignored_message_ids: Vec<i32>,
+    /// Also keeps every logged message for `BaseConfig::take_debug_messages`,
+    /// e.g. to show them in game.
+    pub capture_messages: bool,
+}
+
+impl DebugConfig {
+    fn ignores(&self, message_id_name: &str, message_id_number: i32) -> bool {
+        self.ignored_message_ids.contains(&message_id_number)
+            || self
+                .ignored_message_names
+                .iter()
+                .any(|name| name == message_id_name)
+    }
+}
+
+/// What `debug_callback` reads and writes, reached through the messenger's
+/// user data pointer. Messages may arrive on any thread that makes Vulkan
+/// calls, hence the mutexes.
+pub(super) struct DebugState {
+    config: DebugConfig,
+    validation_errors: Mutex<Vec<String>>,
+    /// Every logged message, when `DebugConfig::capture_messages` is set.
+    captured_messages: Mutex<Vec<String>>,
+}
+
+/// Options that have to be known when the renderer is created.
+#[derive(Clone, Debug)]
+pub struct RendererSettings {
+    /// Presents in HDR10 (`HDR10_ST2084_EXT`) when the surface supports it,
+    /// otherwise falls back to SRGB. Shaders then have to output PQ encoded
+    /// color, see `BaseConfig::hdr_enabled`.
+    pub hdr: bool,
+    /// How many frames the CPU may record while the GPU is still busy with
+    /// earlier ones, from 1 to 3. Fewer frames lower input latency, more
+    /// frames keep the GPU fed when frame times vary.
+    pub frames_in_flight: usize,
+    /// Renders with a negative-height viewport so Y points up in clip space,
+    /// as in OpenGL. This also mirrors the winding of every triangle, so
+    /// materials have to use the opposite `front_face`. Needs Vulkan 1.1.
+    pub flip_viewport_y: bool,
+    /// Presents to an 8-bit UNORM swapchain instead of an SRGB one, so
+    /// shaders encode sRGB themselves rather than the hardware doing it on
+    /// store. See `BaseConfig::shader_encodes_srgb`. Ignored in HDR.
+    pub unorm_swapchain: bool,
+    /// Usage of the swapchain images, on top of `COLOR_ATTACHMENT`. Flags
+    /// the surface does not support are dropped with a warning. Defaults to
+    /// `COLOR_ATTACHMENT | TRANSFER_SRC`, which screenshots need.
+    pub swapchain_usage: ImageUsageFlags,
+    /// Frames with at least this many draws are recorded into secondary
+    /// command buffers on worker threads, smaller ones on the calling
+    /// thread. `None` records every frame on the calling thread, e.g. to
+    /// compare `BaseConfig::last_record_ms` against. Defaults to 1024.
+    pub parallel_recording_min_draws: Option<usize>,
+    pub debug: DebugConfig,
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            hdr: false,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            flip_viewport_y: false,
+            unorm_swapchain: false,
+            swapchain_usage: ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC,
+            parallel_recording_min_draws: Some(PARALLEL_RECORDING_MIN_DRAWS),
+            debug: DebugConfig::default(),
+        }
+    }
+}
+
+/// Output of `BaseConfig::create_core`.
+struct CoreState {
+    context: Arc<DeviceContext>,
     debug_instance: debug_utils::Instance,
-    debug_utils_messenger: DebugUtilsMessengerEXT,
+    debug_utils_messenger: Option<DebugUtilsMessengerEXT>,
+    debug_utils_enabled: bool,
+    surface_instance: surface::Instance,
+    surface: SurfaceKHR,
+    queue_family_indices: QueueFamilyIndices,
+}
+
+/// What the swapchain is created with. The surface format is picked once,
+/// since the render pass and pipelines depend on it; the present mode can be
+/// switched at runtime.
+#[derive(Clone, Copy, Debug)]
+struct SwapchainSettings {
+    surface_format: SurfaceFormatKHR,
+    present_mode: PresentModeKHR,
+    image_usage: ImageUsageFlags,
+}
+
+struct SwapchainSupportDetails {
+    capabilities: SurfaceCapabilitiesKHR,
+    formats: Vec<SurfaceFormatKHR>,
+    present_modes: Vec<PresentModeKHR>,
 }
 
 impl BaseConfig {
-    pub fn init(window: &mut Window) -> Result<BaseConfig, Error> {
+    /// Creates the renderer in stages: `create_core` for what lives as long
+    /// as the renderer, `create_pipeline_state` for what depends on the
+    /// surface format, per-frame resources, and finally
+    /// `create_surface_state` for what depends on the window size. The last
+    /// stage is rerun on its own whenever the swapchain goes out of date.
+    pub fn init(
+        window: &mut Window,
+        settings: RendererSettings,
+    ) -> Result<BaseConfig, EngineError> {
+        unsafe {
+            let debug_state = Box::new(DebugState {
+                config: settings.debug.clone(),
+                validation_errors: Mutex::new(Vec::new()),
+                captured_messages: Mutex::new(Vec::new()),
+            });
+            let CoreState {
+                context,
+                debug_instance,
+                debug_utils_messenger,
+                debug_utils_enabled,
+                surface_instance,
+                surface,
+                queue_family_indices,
+            } = Self::create_core(window, &settings, debug_state)?;
+            let instance = &context.instance;
+            let physical_device = context.physical_device;
+            let device = &context.device;
+
+            let pipeline_cache = create_pipeline_cache(instance, physical_device, device);
+
+            let memory_budget_supported = supports_memory_budget(instance, physical_device);
+            let device_fault = supports_device_fault(instance, physical_device)
+                .then(|| device_fault::Device::new(instance, device));
+            let conditional_rendering = supports_conditional_rendering(instance, physical_device)
+                .then(|| conditional_rendering::Device::new(instance, device));
+            let debug_utils_device =
+                debug_utils_enabled.then(|| debug_utils::Device::new(instance, device));
+            let hdr_metadata = (settings.hdr
+                && check_device_extension_support(instance, physical_device, hdr_metadata::NAME))
+            .then(|| hdr_metadata::Device::new(instance, device));
+
+            let graphics_queue = device.get_device_queue(queue_family_indices.graphics_family, 0);
+            let presentation_queue = if queue_family_indices.is_shared() {
+                None
+            } else {
+                Some(device.get_device_queue(queue_family_indices.presentation_family, 0))
+            };
+
+            let swapchain_device = swapchain::Device::new(instance, device);
+            let window_size = window.inner_size();
+            let window_extent = Extent2D {
+                width: window_size.width,
+                height: window_size.height,
+            };
+            let support_details =
+                query_swapchain_support_details(&surface_instance, physical_device, surface);
+            let surface_format = choose_surface_format(
+                &support_details.formats,
+                settings.hdr,
+                settings.unorm_swapchain,
+            )
+            .expect("Surface reports no formats");
+            let swapchain_settings = SwapchainSettings {
+                surface_format,
+                present_mode: choose_present_mode(&support_details.present_modes),
+                image_usage: supported_image_usage(
+                    settings.swapchain_usage,
+                    support_details.capabilities.supported_usage_flags,
+                ),
+            };
+
+            let bindless_textures = supports_descriptor_indexing(instance, physical_device)
+                .then(|| BindlessTextures::new(&context));
+            // Set 0 stays empty without bindless textures, so the uniform set
+            // keeps its number.
+            let empty_set_layout = bindless_textures.is_none().then(|| {
+                device
+                    .create_descriptor_set_layout(&DescriptorSetLayoutCreateInfo::default(), None)
+                    .expect("Failed to create empty descriptor set layout")
+            });
+            let uniform_set_layout = create_uniform_set_layout(device);
+            let set_layouts = [
+                bindless_textures
+                    .as_ref()
+                    .map(BindlessTextures::set_layout)
+                    .or(empty_set_layout)
+                    .unwrap(),
+                uniform_set_layout,
+            ];
+            let depth_stencil_format = find_depth_stencil_format(instance, physical_device);
+            if depth_stencil_format.is_none() {
+                println!(
+                    "No depth/stencil format supported, rendering without depth or stencil tests"
+                );
+            }
+            let (render_pass, pipeline_layout) = create_pipeline_state(
+                device,
+                surface_format.format,
+                depth_stencil_format,
+                &set_layouts,
+            );
+
+            // Negative viewport heights came with VK_KHR_maintenance1, core
+            // in Vulkan 1.1.
+            let flip_viewport_y = settings.flip_viewport_y
+                && instance
+                    .get_physical_device_properties(physical_device)
+                    .api_version
+                    >= vk::API_VERSION_1_1;
+            if settings.flip_viewport_y && !flip_viewport_y {
+                println!("Flipping the viewport needs Vulkan 1.1, keeping Y down");
+            }
+
+            let frames_in_flight = if FRAMES_IN_FLIGHT_RANGE.contains(&settings.frames_in_flight) {
+                settings.frames_in_flight
+            } else {
+                println!(
+                    "Unsupported frames in flight {}, using {DEFAULT_FRAMES_IN_FLIGHT}",
+                    settings.frames_in_flight
+                );
+                DEFAULT_FRAMES_IN_FLIGHT
+            };
+
+            let command_pool = device
+                .create_command_pool(
+                    &CommandPoolCreateInfo::default()
+                        .flags(CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                        .queue_family_index(queue_family_indices.graphics_family),
+                    None,
+                )
+                .expect("Failed to create command pool");
+            let command_buffers = device
+                .allocate_command_buffers(
+                    &CommandBufferAllocateInfo::default()
+                        .command_pool(command_pool)
+                        .level(CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(frames_in_flight as u32),
+                )
+                .expect("Failed to allocate command buffers");
+
+            let parallel_recorder = ParallelRecorder::new(
+                &context,
+                queue_family_indices.graphics_family,
+                frames_in_flight,
+                thread::available_parallelism().map_or(1, |workers| workers.get()),
+            );
+
+            let (timestamp_query_pool, timestamp_mask) = create_timestamp_query_pool(
+                instance,
+                physical_device,
+                device,
+                queue_family_indices.graphics_family,
+                frames_in_flight,
+            );
+            let timestamp_period = instance
+                .get_physical_device_properties(physical_device)
+                .limits
+                .timestamp_period;
+
+            let image_available_semaphores = create_semaphores(device, frames_in_flight);
+            let frame_timeline = supports_timeline_semaphore(instance, physical_device)
+                .then(|| FrameTimeline::new(instance, device));
+            let mut in_flight_fences = Vec::with_capacity(frames_in_flight);
+            for _ in 0..frames_in_flight {
+                if frame_timeline.is_none() {
+                    in_flight_fences.push(
+                        device
+                            .create_fence(
+                                &FenceCreateInfo::default().flags(FenceCreateFlags::SIGNALED),
+                                None,
+                            )
+                            .expect("Failed to create fence"),
+                    );
+                }
+            }
+            println!(
+                "Frame pacing: {}",
+                if frame_timeline.is_some() {
+                    "timeline semaphore"
+                } else {
+                    "fences"
+                }
+            );
+
+            let frame_descriptor_allocators = (0..frames_in_flight)
+                .map(|_| {
+                    DescriptorAllocator::new(
+                        &context,
+                        FRAME_DESCRIPTOR_SETS,
+                        &FRAME_DESCRIPTOR_POOL_RATIOS,
+                    )
+                })
+                .collect();
+
+            let mut base_config = Self {
+                context,
+                debug_instance,
+                debug_utils_messenger,
+                debug_utils_device,
+                surface_instance,
+                surface,
+                memory_budget_supported,
+                device_fault,
+                conditional_rendering,
+                bindless_textures,
+                pipeline_cache,
+                graphics_queue,
+                presentation_queue,
+                queue_family_indices,
+                swapchain_device,
+                // Filled in by `create_surface_state` below.
+                swapchain: SwapchainKHR::null(),
+                swapchain_settings,
+                hdr_metadata,
+                swapchain_image_views: Vec::new(),
+                swapchain_extent: Extent2D::default(),
+                window_extent,
+                swapchain_outdated: false,
+                render_pass,
+                depth_stencil_format,
+                depth_stencil_image: None,
+                framebuffers: Vec::new(),
+                pipeline_layout,
+                empty_set_layout,
+                uniform_set_layout,
+                materials: Vec::new(),
+                material_names: HashMap::new(),
+                pipelines: HashMap::new(),
+                pending_draws: Vec::new(),
+                clear_color: [0.0, 0.0, 0.0, 1.0],
+                command_pool,
+                command_buffers,
+                parallel_recorder,
+                parallel_recording_min_draws: settings.parallel_recording_min_draws,
+                image_available_semaphores,
+                render_finished_semaphores: Vec::new(),
+                in_flight_fences,
+                frame_timeline,
+                frames_submitted: 0,
+                frame_timer: FrameTimer::new(None),
+                deletion_queue: DeletionQueue::default(),
+                frame_descriptor_allocators,
+                frames_in_flight,
+                flip_viewport_y,
+                current_frame: 0,
+                timestamp_query_pool,
+                timestamp_period,
+                timestamp_mask,
+                timestamps_written: vec![false; frames_in_flight],
+                last_gpu_frame_ms: None,
+                last_record_ms: None,
+            };
+            base_config.create_surface_state();
+            Ok(base_config)
+        }
+    }
+
+    /// Loads Vulkan and creates the instance, debug messenger, window
+    /// surface and device. The surface belongs here rather than with the
+    /// swapchain, since picking a device depends on it supporting
+    /// presentation to that surface.
+    fn create_core(
+        window: &Window,
+        settings: &RendererSettings,
+        debug_state: Box<DebugState>,
+    ) -> Result<CoreState, EngineError> {
         unsafe {
-            let entry = Entry::load().expect("No vulkan library found on this machine");
+            let entry = Entry::load().map_err(EngineError::NoVulkanLoader)?;
 
             let mut debug_info = DebugUtilsMessengerCreateInfoEXT::default()
                 .message_severity(
@@ -46,22 +680,30 @@ impl BaseConfig {
                         | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
                         | DebugUtilsMessageTypeFlagsEXT::DEVICE_ADDRESS_BINDING,
                 )
-                .pfn_user_callback(Some(debug_callback));
+                .pfn_user_callback(Some(debug_callback))
+                .user_data(ptr::from_ref(debug_state.as_ref()).cast_mut().cast());
 
-            let instance = Self::create_instance(window, &entry, &mut debug_info)
-                .expect("Failed to create instance");
+            let debug_utils_enabled =
+                Self::check_instance_extension_support(&entry, debug_utils::NAME);
+            // Without it, surfaces only report SRGB color spaces.
+            let swapchain_colorspace_enabled = settings.hdr
+                && Self::check_instance_extension_support(&entry, swapchain_colorspace::NAME);
+            let instance = Self::create_instance(
+                window,
+                &entry,
+                debug_utils_enabled.then_some(&mut debug_info),
+                swapchain_colorspace_enabled,
+            )
+            .expect("Failed to create instance");
 
             let debug_instance = debug_utils::Instance::new(&entry, &instance);
-            let debug_utils_messenger = debug_instance
-                .create_debug_utils_messenger(&debug_info, None)
-                .expect("Failed to create debug messenger");
-
-            let device = create_device(&instance, QueueFlags::GRAPHICS)
-                .expect("Failed to create logical device");
+            let debug_utils_messenger = debug_utils_enabled.then(|| {
+                debug_instance
+                    .create_debug_utils_messenger(&debug_info, None)
+                    .expect("Failed to create debug messenger")
+            });
 
             let surface_instance = surface::Instance::new(&entry, &instance);
-            let swapchain_instance = swapchain::Instance::new(&entry, &instance);
-
             let surface = ash_window::create_surface(
                 &entry,
                 &instance,
@@ -71,218 +713,2951 @@ impl BaseConfig {
             )
             .expect("Failed to create surface");
 
-            Ok(Self {
-                instance: instance,
-                debug_instance: debug_instance,
-                debug_utils_messenger: debug_utils_messenger,
+            let created = create_device(
+                &instance,
+                &surface_instance,
+                surface,
+                QueueFlags::GRAPHICS,
+                settings.hdr,
+            );
+            let (physical_device, device, queue_family_indices, device_name) = match created {
+                Ok(created) => created,
+                Err(err) => {
+                    // The messenger and the instance's own debug callback
+                    // point at `debug_state`, which is freed on return.
+                    if let Some(messenger) = debug_utils_messenger {
+                        debug_instance.destroy_debug_utils_messenger(messenger, None);
+                    }
+                    surface_instance.destroy_surface(surface, None);
+                    instance.destroy_instance(None);
+                    return Err(err);
+                }
+            };
+            println!("Running on {device_name}");
+            let context = Arc::new(DeviceContext {
+                entry,
+                instance,
+                physical_device,
+                device,
+                debug_state,
+            });
+            Ok(CoreState {
+                context,
+                debug_instance,
+                debug_utils_messenger,
+                debug_utils_enabled,
+                surface_instance,
+                surface,
+                queue_family_indices,
             })
         }
     }
 
-    fn create_instance(
-        window: &mut Window,
-        entry: &Entry,
-        debug_info: &mut DebugUtilsMessengerCreateInfoEXT,
-    ) -> Result<Instance, Error> {
+    /// Queue used for `queue_present`. Falls back to the graphics queue when
+    /// both capabilities are served by the same family.
+    fn present_queue(&self) -> Queue {
+        self.presentation_queue.unwrap_or(self.graphics_queue)
+    }
+
+    /// Records the new window size; the swapchain is rebuilt lazily at the
+    /// start of the next frame.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.window_extent = Extent2D { width, height };
+        self.swapchain_outdated = true;
+    }
+
+    /// Creates the swapchain and everything sized from it: image views,
+    /// framebuffers and render-finished semaphores. When rebuilding, the
+    /// current swapchain is handed to the driver as `old_swapchain` so it can
+    /// reuse its resources, and is only destroyed once the replacement
+    /// exists. On the first call there is nothing to replace yet, and the
+    /// null handles destroyed later are ignored by Vulkan.
+    fn create_surface_state(&mut self) {
         unsafe {
-            let application_name = b"Malbi\0";
-            let app_info = ApplicationInfo::default()
-                .api_version(0)
-                .engine_name(ffi::CStr::from_bytes_with_nul_unchecked(b"No Engine\0"))
-                .engine_version(1)
-                .application_version(1)
-                .application_name(ffi::CStr::from_bytes_with_nul_unchecked(application_name));
+            let old_swapchain = self.swapchain;
+            let support_details = query_swapchain_support_details(
+                &self.surface_instance,
+                self.context.physical_device,
+                self.surface,
+            );
+            let (swapchain, swapchain_extent) = create_swapchain(
+                &self.swapchain_device,
+                &support_details,
+                self.surface,
+                self.swapchain_settings,
+                self.queue_family_indices,
+                self.window_extent,
+                old_swapchain,
+            );
+            if let Some(hdr_metadata) = &self.hdr_metadata {
+                set_hdr_metadata(
+                    hdr_metadata,
+                    swapchain,
+                    self.swapchain_settings.surface_format,
+                );
+            }
 
-            let raw_display_handle = window
-                .display_handle()
-                .expect("failed to retrieve display handle")
-                .as_raw();
+            // Frames still in flight may reference the old images, so they
+            // are only released once those frames have completed.
+            let context = Arc::clone(&self.context);
+            let swapchain_device = self.swapchain_device.clone();
+            let old_framebuffers = std::mem::take(&mut self.framebuffers);
+            let old_image_views = std::mem::take(&mut self.swapchain_image_views);
+            let old_depth_stencil_image = self.depth_stencil_image.take();
+            self.deletion_queue.push(self.frames_submitted, move || {
+                for framebuffer in old_framebuffers {
+                    context.device.destroy_framebuffer(framebuffer, None);
+                }
+                for image_view in old_image_views {
+                    context.device.destroy_image_view(image_view, None);
+                }
+                drop(old_depth_stencil_image);
+                swapchain_device.destroy_swapchain(old_swapchain, None);
+            });
 
-            let enumerate_required_extensions =
-                ash_window::enumerate_required_extensions(raw_display_handle)
-                    .expect("Failed to enumerate required extensions");
+            let swapchain_images = self
+                .swapchain_device
+                .get_swapchain_images(swapchain)
+                .expect("Failed to retrieve swapchain images");
+            if self.swapchain_settings.present_mode == PresentModeKHR::MAILBOX
+                && swapchain_images.len() < 3
+            {
+                println!(
+                    "MAILBOX with only {} swapchain images, frames may block",
+                    swapchain_images.len()
+                );
+            }
+            if swapchain_images.len() != self.render_finished_semaphores.len() {
+                let context = Arc::clone(&self.context);
+                let old_semaphores = std::mem::replace(
+                    &mut self.render_finished_semaphores,
+                    create_semaphores(&self.context.device, swapchain_images.len()),
+                );
+                self.deletion_queue.push(self.frames_submitted, move || {
+                    for semaphore in old_semaphores {
+                        context.device.destroy_semaphore(semaphore, None);
+                    }
+                });
+            }
+            self.swapchain_image_views = create_image_views(
+                &self.context.device,
+                &swapchain_images,
+                self.swapchain_settings.surface_format.format,
+            );
+            self.depth_stencil_image = self.depth_stencil_format.map(|format| {
+                ResourceImage::new(
+                    &self.context,
+                    swapchain_extent,
+                    format,
+                    ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                    ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL,
+                )
+            });
+            self.framebuffers = create_framebuffers(
+                &self.context.device,
+                self.render_pass,
+                &self.swapchain_image_views,
+                self.depth_stencil_image.as_ref().map(ResourceImage::view),
+                swapchain_extent,
+            );
+            self.swapchain = swapchain;
+            self.swapchain_extent = swapchain_extent;
+            self.swapchain_outdated = false;
+        }
+    }
 
-            let mut required_extensions = enumerate_required_extensions.to_vec();
-            required_extensions.push(ash::vk::KHR_PORTABILITY_ENUMERATION_NAME.as_ptr());
-            required_extensions.push(debug_utils::NAME.as_ptr());
+    /// Number of submitted frames guaranteed to have completed once the slot
+    /// of the next frame has been waited on.
+    fn frames_completed(&self) -> u64 {
+        (self.frames_submitted + 1).saturating_sub(self.frames_in_flight as u64)
+    }
 
-            let validation_layer = [ffi::CStr::from_bytes_with_nul_unchecked(
-                b"VK_LAYER_KHRONOS_validation\0",
-            )];
+    /// Name of the physical device the engine runs on.
+    pub fn adapter_name(&self) -> String {
+        let properties = unsafe {
+            self.context
+                .instance
+                .get_physical_device_properties(self.context.physical_device)
+        };
+        properties
+            .device_name_as_c_str()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
 
-            let layer_names = validation_layer.map(|layer| layer.as_ptr()).to_vec();
-            let validation_layers_enabled =
-                Self::check_validation_layer_support(&entry, &layer_names);
+    pub fn swapchain_extent(&self) -> Extent2D {
+        self.swapchain_extent
+    }
 
-            let mut instance_create_info = InstanceCreateInfo::default()
-                .application_info(&app_info)
-                .enabled_extension_names(&required_extensions)
-                .flags(InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR)
-                .push_next(debug_info);
+    /// Number of images the driver actually created, which may exceed the
+    /// minimum requested. Resources indexed by the acquired image, such as
+    /// framebuffers and render-finished semaphores, are sized from it.
+    pub fn swapchain_image_count(&self) -> usize {
+        self.swapchain_image_views.len()
+    }
 
-            if validation_layers_enabled {
-                instance_create_info = instance_create_info.enabled_layer_names(&layer_names);
-                println!("XDD {:?}", instance_create_info.enabled_layer_count);
-            }
+    /// Whether the swapchain presents HDR10. Shaders must then output color
+    /// encoded with the ST 2084 (PQ) curve in BT.2020 primaries.
+    pub fn hdr_enabled(&self) -> bool {
+        self.swapchain_settings.surface_format.color_space == ColorSpaceKHR::HDR10_ST2084_EXT
+    }
 
-            let instance = entry
-                .create_instance(&instance_create_info, None)
-                .expect("Failed to create instance");
+    /// Whether fragment shaders have to output sRGB encoded color. True for
+    /// UNORM swapchains, false for SRGB ones, which encode the linear color
+    /// shaders write, and for HDR. Materials read it from the
+    /// `ENCODE_SRGB_CONSTANT_ID` specialization constant.
+    pub fn shader_encodes_srgb(&self) -> bool {
+        let surface_format = self.swapchain_settings.surface_format;
+        surface_format.color_space == ColorSpaceKHR::SRGB_NONLINEAR
+            && !is_srgb_format(surface_format.format)
+    }
 
-            Ok(instance)
-        }
+    /// The usage the swapchain images were created with, i.e. the requested
+    /// `RendererSettings::swapchain_usage` minus unsupported flags.
+    pub fn swapchain_usage(&self) -> ImageUsageFlags {
+        self.swapchain_settings.image_usage
     }
 
-    fn check_validation_layer_support(entry: &Entry, used_layer_names: &[*const i8]) -> bool {
-        unsafe {
-            let layer_properties = entry
-                .enumerate_instance_layer_properties()
-                .expect("Failed to enumerate instance layer properties");
+    pub fn present_mode(&self) -> PresentModeKHR {
+        self.swapchain_settings.present_mode
+    }
 
-            let mut flag = false;
-            for name in used_layer_names {
-                match layer_properties.iter().find(|&layer_property| {
-                    !layer_property
-                        .layer_name_as_c_str()
-                        .expect("failed to create layer property")
-                        .is_empty()
-                }) {
-                    Some(_layer_prop) => {
-                        flag = true;
-                        break;
-                    }
-                    None => {
-                        flag = false;
-                    }
-                };
-            }
-            flag
+    /// Switches to the next present mode the surface supports and rebuilds
+    /// the swapchain before the next frame. Returns the new mode, or `None`
+    /// when there is nothing to switch to.
+    pub fn cycle_present_mode(&mut self) -> Option<PresentModeKHR> {
+        let present_modes = unsafe {
+            self.surface_instance
+                .get_physical_device_surface_present_modes(
+                    self.context.physical_device,
+                    self.surface,
+                )
+                .expect("Failed to query surface present modes")
+        };
+        if present_modes.len() < 2 {
+            return None;
         }
+        let current = present_modes
+            .iter()
+            .position(|&mode| mode == self.swapchain_settings.present_mode)
+            .unwrap_or(0);
+        let present_mode = present_modes[(current + 1) % present_modes.len()];
+        self.swapchain_settings.present_mode = present_mode;
+        self.swapchain_outdated = true;
+        Some(present_mode)
     }
-}
 
-fn create_device(
-    instance: &Instance,
-    queue_flag: QueueFlags,
-) -> Result<(PhysicalDevice, Device), Error> {
-    unsafe {
-        let enumerated_physical_devices = instance
-            .enumerate_physical_devices()
-            .expect("Failed to enumerate physical devices");
-        let mut phy_device: Option<PhysicalDevice> = None;
-        for physical_device in enumerated_physical_devices {
-            if physical_device_suitability(instance, physical_device, queue_flag) {
-                phy_device = Some(physical_device);
-                break;
-            }
-        }
-
-        return match phy_device {
-            Some(physical_device) => {
-                let queue_create_info =
-                    vec![
-                        match find_queue_family_index(instance, &physical_device, queue_flag) {
-                            Some(idx) => Ok(DeviceQueueCreateInfo::default()
-                                .queue_family_index(idx as u32)
-                                .queue_priorities(&[1.0])),
-                            None => Err(Error::new(
-                                ErrorKind::NotFound,
-                                "No suitable physical device found!",
-                            )),
-                        }
-                        .expect("Failed to create DeviceQueueCreateInfos"),
-                    ];
-
-                let physical_devices_feature =
-                    &instance.get_physical_device_features(phy_device.unwrap());
-                let device_create_info = DeviceCreateInfo::default()
-                    .enabled_features(&physical_devices_feature)
-                    .queue_create_infos(&queue_create_info);
-
-                Ok((
-                    physical_device,
-                    instance
-                        .create_device(physical_device, &device_create_info, None)
-                        .expect("Failed to create a logical device"),
-                ))
-            }
-            None => Err(Error::new(
-                ErrorKind::NotFound,
-                "No suitable physical device found!",
-            )),
+    /// Whether clip space Y points up, see `RendererSettings::flip_viewport_y`.
+    pub fn viewport_flipped(&self) -> bool {
+        self.flip_viewport_y
+    }
+
+    /// Optional features and limits of the device the engine runs on.
+    pub fn capabilities(&self) -> Capabilities {
+        query_capabilities(&self.context.instance, self.context.physical_device)
+    }
+
+    /// Highest sample count usable for color and depth attachments alike,
+    /// i.e. the most MSAA a render pass with both can use.
+    pub fn max_usable_sample_count(&self) -> SampleCountFlags {
+        let limits = unsafe {
+            self.context
+                .instance
+                .get_physical_device_properties(self.context.physical_device)
+                .limits
         };
-    };
-}
+        let counts =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        [
+            SampleCountFlags::TYPE_64,
+            SampleCountFlags::TYPE_32,
+            SampleCountFlags::TYPE_16,
+            SampleCountFlags::TYPE_8,
+            SampleCountFlags::TYPE_4,
+            SampleCountFlags::TYPE_2,
+        ]
+        .into_iter()
+        .find(|&count| counts.contains(count))
+        .unwrap_or(SampleCountFlags::TYPE_1)
+    }
 
-fn physical_device_suitability(
-    instance: &Instance,
-    physical_device: vk::PhysicalDevice,
-    queue_flag: QueueFlags,
-) -> bool {
-    unsafe {
-        let physical_device_properties = instance.get_physical_device_properties(physical_device);
+    /// GPU time spent in the render pass of the most recently completed
+    /// frame, or `None` when the graphics queue does not support timestamps.
+    pub fn last_gpu_frame_ms(&self) -> Option<f32> {
+        self.last_gpu_frame_ms
+    }
 
-        return if physical_device_properties.device_type == PhysicalDeviceType::INTEGRATED_GPU
-            && find_queue_family_index(instance, &physical_device, queue_flag).is_some()
-        {
-            true
-        } else {
-            false
+    /// CPU time spent recording the draws of the last frame, on worker
+    /// threads or not, or `None` before the first frame.
+    pub fn last_record_ms(&self) -> Option<f32> {
+        self.last_record_ms
+    }
+
+    /// Reads back the timestamps written the last time this frame slot was
+    /// recorded. Must only be called once the slot's in-flight fence signalled.
+    fn read_frame_timestamps(&mut self) {
+        let Some(query_pool) = self.timestamp_query_pool else {
+            return;
+        };
+        if !self.timestamps_written[self.current_frame] {
+            return;
+        }
+
+        let mut timestamps = [0u64; 2];
+        let read = unsafe {
+            self.context.device.get_query_pool_results(
+                query_pool,
+                (self.current_frame * 2) as u32,
+                &mut timestamps,
+                QueryResultFlags::TYPE_64 | QueryResultFlags::WAIT,
+            )
         };
+        if read.is_ok() {
+            let ticks = timestamps[1].wrapping_sub(timestamps[0]) & self.timestamp_mask;
+            self.last_gpu_frame_ms = Some(ticks as f32 * self.timestamp_period / 1_000_000.0);
+        }
     }
-}
 
-fn find_queue_family_index(
-    instance: &Instance,
-    physical_device: &PhysicalDevice,
-    queue_flag: QueueFlags,
-) -> Option<usize> {
-    unsafe {
-        instance
-            .get_physical_device_queue_family_properties(*physical_device)
-            .iter()
-            .enumerate()
-            .find_map(|(idx, queue_property)| {
-                if queue_property.queue_flags.contains(queue_flag) {
-                    Some(idx)
-                } else {
-                    None
-                }
-            })
+    /// Validation errors reported since the last call, oldest first, leaving
+    /// out ignored messages. Asserting this is empty after rendering turns
+    /// validation into a pass/fail check. Always empty without
+    /// `VK_EXT_debug_utils`.
+    pub fn take_validation_errors(&self) -> Vec<String> {
+        std::mem::take(&mut self.context.debug_state.validation_errors.lock().unwrap())
     }
-}
 
-unsafe extern "system" fn debug_callback(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
-    _user_data: *mut std::os::raw::c_void,
-) -> u32 {
-    let callback_data = *p_callback_data;
-    let message_id_number = callback_data.message_id_number;
-    let message_id_name = if callback_data.p_message_id_name.is_null() {
-        Cow::from("")
-    } else {
-        ffi::CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy()
-    };
+    /// Messages logged since the last call, formatted as printed. Always empty
+    /// unless `DebugConfig::capture_messages` is set.
+    pub fn take_debug_messages(&self) -> Vec<String> {
+        std::mem::take(&mut self.context.debug_state.captured_messages.lock().unwrap())
+    }
 
-    let message = if callback_data.p_message.is_null() {
-        Cow::from("")
-    } else {
-        ffi::CStr::from_ptr(callback_data.p_message).to_string_lossy()
-    };
-    println!(
-        "{message_severity:?}:{message_type:?}:{message_id_name} {message_id_number}:{message}\n"
-    );
-    vk::FALSE
-}
+    /// Per-heap memory budget and usage as reported by `VK_EXT_memory_budget`.
+    /// Empty when the device does not support the extension.
+    pub fn memory_budget(&self) -> Vec<HeapBudget> {
+        if !self.memory_budget_supported {
+            return Vec::new();
+        }
 
-impl Drop for BaseConfig {
-    fn drop(&mut self) {
         unsafe {
-            self.instance.destroy_instance(None);
-            self.debug_instance
-                .destroy_debug_utils_messenger(self.debug_utils_messenger, None);
-        };
+            let mut budget_properties = PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+            let mut memory_properties =
+                PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_properties);
+            self.context
+                .instance
+                .get_physical_device_memory_properties2(
+                    self.context.physical_device,
+                    &mut memory_properties,
+                );
+            let memory_properties = memory_properties.memory_properties;
+
+            memory_properties
+                .memory_heaps_as_slice()
+                .iter()
+                .enumerate()
+                .map(|(heap_index, heap)| HeapBudget {
+                    heap_index: heap_index as u32,
+                    device_local: heap.flags.contains(MemoryHeapFlags::DEVICE_LOCAL),
+                    budget: budget_properties.heap_budget[heap_index],
+                    usage: budget_properties.heap_usage[heap_index],
+                })
+                .collect()
+        }
+    }
+
+    /// Opens a named, colored region in `command_buffer` for graphics
+    /// debuggers. Does nothing when `VK_EXT_debug_utils` is unavailable.
+    pub fn push_debug_label(
+        &self,
+        command_buffer: CommandBuffer,
+        name: &ffi::CStr,
+        color: [f32; 4],
+    ) {
+        if let Some(debug_utils_device) = &self.debug_utils_device {
+            unsafe {
+                debug_utils_device.cmd_begin_debug_utils_label(
+                    command_buffer,
+                    &DebugUtilsLabelEXT::default().label_name(name).color(color),
+                );
+            }
+        }
+    }
+
+    /// Closes the region opened by the matching `push_debug_label`.
+    pub fn pop_debug_label(&self, command_buffer: CommandBuffer) {
+        if let Some(debug_utils_device) = &self.debug_utils_device {
+            unsafe {
+                debug_utils_device.cmd_end_debug_utils_label(command_buffer);
+            }
+        }
+    }
+
+    /// Registers a material and returns its id. The SPIR-V is read now; the
+    /// pipeline itself is only built the first time the material is drawn.
+    /// Fails with `ErrorKind::Unsupported` when the material needs a shader
+    /// stage the device lacks, and with `ErrorKind::InvalidInput` for
+    /// descriptions that can never work, e.g. deriving from a material not
+    /// registered yet. `ENCODE_SRGB_CONSTANT_ID` is added to the
+    /// specialization constants unless `desc` sets it.
+    pub fn register_material(&mut self, mut desc: MaterialDesc) -> Result<MaterialId, Error> {
+        let capabilities = self.capabilities();
+        if desc.geometry_shader.is_some() && !capabilities.geometry_shader {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Geometry shaders are not supported by this device",
+            ));
+        }
+        if let Some(tessellation) = &desc.tessellation {
+            if !capabilities.tessellation_shader {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Tessellation shaders are not supported by this device",
+                ));
+            }
+            if desc.topology != PrimitiveTopology::PATCH_LIST {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Tessellation requires PrimitiveTopology::PATCH_LIST",
+                ));
+            }
+            let max_patch_size = unsafe {
+                self.context
+                    .instance
+                    .get_physical_device_properties(self.context.physical_device)
+                    .limits
+                    .max_tessellation_patch_size
+            };
+            if tessellation.patch_control_points == 0
+                || tessellation.patch_control_points > max_patch_size
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Patches must have between 1 and {max_patch_size} control points"),
+                ));
+            }
+        }
+        if desc
+            .depth_bias
+            .is_some_and(|depth_bias| depth_bias.clamp != 0.0)
+            && unsafe {
+                self.context
+                    .instance
+                    .get_physical_device_features(self.context.physical_device)
+                    .depth_bias_clamp
+                    == vk::FALSE
+            }
+        {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Depth bias clamping is not supported by this device",
+            ));
+        }
+        if desc.stencil.is_some() && !self.stencil_supported() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Stencil state needs a depth/stencil format this device lacks",
+            ));
+        }
+        if desc.dynamic_line_width {
+            if capabilities.wide_lines {
+                // Recorded as is by draws that don't set their own.
+                desc.line_width = supported_line_width(&self.context, desc.line_width);
+            } else {
+                println!("Wide lines are not supported, line width stays 1.0");
+                desc.dynamic_line_width = false;
+            }
+        }
+        if let Some(base_material) = desc.derive_from {
+            if base_material.index() >= self.materials.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Materials can only derive from materials registered before them",
+                ));
+            }
+            // A base pipeline needs ALLOW_DERIVATIVES from the start.
+            if self.pipelines.contains_key(&base_material) {
+                println!("{base_material:?} already has a pipeline, not deriving from it");
+                desc.derive_from = None;
+            }
+        }
+        if !desc
+            .specialization_constants
+            .iter()
+            .any(|&(constant_id, _)| constant_id == ENCODE_SRGB_CONSTANT_ID)
+        {
+            desc.specialization_constants.push((
+                ENCODE_SRGB_CONSTANT_ID,
+                SpecConstant::Bool(self.shader_encodes_srgb()),
+            ));
+        }
+        self.materials.push(Material::load(desc)?);
+        Ok(MaterialId::new(self.materials.len() - 1))
+    }
+
+    /// Registers a material that can later be looked up by `name`, e.g. to
+    /// switch between shader programs at runtime. Names must be unique.
+    pub fn register_named_material(
+        &mut self,
+        name: &str,
+        desc: MaterialDesc,
+    ) -> Result<MaterialId, Error> {
+        if self.material_names.contains_key(name) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("A material named {name:?} is already registered"),
+            ));
+        }
+        let material = self.register_material(desc)?;
+        self.material_names.insert(name.to_owned(), material);
+        Ok(material)
+    }
+
+    /// The material registered under `name`, if any.
+    pub fn material(&self, name: &str) -> Option<MaterialId> {
+        self.material_names.get(name).copied()
+    }
+
+    /// Sets the color the swapchain image is cleared to before drawing, as
+    /// linear RGBA. Stays in effect for later frames. Defaults to opaque
+    /// black.
+    pub fn set_clear_color(&mut self, rgba: [f32; 4]) {
+        self.clear_color = rgba;
+    }
+
+    /// Queues `vertex_count` vertices to be drawn with `material` in the next
+    /// frame. Opaque draws are recorded grouped by material, in the order the
+    /// materials were registered.
+    pub fn draw(&mut self, material: MaterialId, vertex_count: u32) {
+        self.draw_with_push_constants(material, vertex_count, &[]);
+    }
+
+    /// Like `draw`, with `push_constants` pushed at offset 0 for the vertex
+    /// and fragment stages first.
+    pub fn draw_with_push_constants(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+    ) {
+        self.draw_transparent(material, vertex_count, push_constants, 0.0);
+    }
+
+    /// Like `draw_with_push_constants`, for materials that blend. Transparent
+    /// draws are recorded after all opaque ones, farthest `camera_distance`
+    /// first; draws at the same distance keep their submission order.
+    pub fn draw_transparent(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        camera_distance: f32,
+    ) {
+        assert!(
+            push_constants.len() <= PUSH_CONSTANT_SIZE && push_constants.len().is_multiple_of(4),
+            "Push constants must be a multiple of 4 bytes and at most {PUSH_CONSTANT_SIZE} bytes"
+        );
+        let desc = &self.materials[material.index()].desc;
+        let depth_bias = desc
+            .dynamic_depth_bias
+            .then(|| desc.depth_bias.unwrap_or_default());
+        let line_width = desc.dynamic_line_width.then_some(desc.line_width);
+        self.pending_draws.push(DrawCall {
+            material,
+            vertex_count,
+            push_constants: push_constants.to_vec(),
+            camera_distance,
+            predicate: None,
+            depth_bias,
+            line_width,
+            uniforms: None,
+        });
+    }
+
+    /// Like `draw_with_push_constants`, with `depth_bias` instead of the
+    /// material's own. Fails with `ErrorKind::InvalidInput`, without queuing
+    /// the draw, unless `material` has `dynamic_depth_bias` set.
+    pub fn draw_with_depth_bias(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        depth_bias: DepthBias,
+    ) -> Result<(), Error> {
+        if !self.materials[material.index()].desc.dynamic_depth_bias {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{material:?} does not have dynamic depth bias"),
+            ));
+        }
+        self.draw_with_push_constants(material, vertex_count, push_constants);
+        self.pending_draws.last_mut().unwrap().depth_bias = Some(depth_bias);
+        Ok(())
+    }
+
+    /// Like `draw_with_push_constants`, with lines `line_width` wide, clamped
+    /// to what the device supports. Uses the material's `line_width` unless
+    /// it was registered with `dynamic_line_width` on a device with
+    /// `wide_lines`.
+    pub fn draw_with_line_width(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        line_width: f32,
+    ) {
+        self.draw_with_push_constants(material, vertex_count, push_constants);
+        if self.materials[material.index()].desc.dynamic_line_width {
+            self.pending_draws.last_mut().unwrap().line_width =
+                Some(clamp_line_width(&self.context, line_width));
+        }
+    }
+
+    /// Like `draw_with_push_constants`, but the GPU skips the draw when the
+    /// 32-bit value at `offset` in `predicate` is zero when it executes, with
+    /// no readback on the CPU. The buffer must come from
+    /// `create_predicate_buffer` and stay alive until the frame completes.
+    /// Without `VK_EXT_conditional_rendering` the draw is always made.
+    pub fn draw_conditional(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        predicate: &MappedBuffer,
+        offset: DeviceSize,
+    ) {
+        assert!(
+            offset.is_multiple_of(4) && offset + 4 <= predicate.buffer().size(),
+            "Predicate offset must be 4 byte aligned and inside the buffer"
+        );
+        self.draw_with_push_constants(material, vertex_count, push_constants);
+        if self.conditional_rendering.is_some() {
+            self.pending_draws.last_mut().unwrap().predicate =
+                Some((predicate.buffer().handle(), offset));
+        }
+    }
+
+    /// Like `draw_with_push_constants`, with element `index` of `uniforms`
+    /// bound to `layout(set = 1, binding = 0) uniform`, so many draws can
+    /// share one buffer of per-object data. The buffer must stay alive until
+    /// the frame completes.
+    pub fn draw_with_uniforms(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        uniforms: &DynamicUniformBuffer,
+        index: usize,
+    ) {
+        let dynamic_offset = uniforms.dynamic_offset(index);
+        self.draw_with_push_constants(material, vertex_count, push_constants);
+        self.pending_draws.last_mut().unwrap().uniforms = Some(DrawUniforms {
+            buffer: uniforms.buffer().handle(),
+            range: uniforms.stride(),
+            dynamic_offset,
+            set: DescriptorSet::null(),
+        });
+    }
+
+    /// Creates a buffer with a `size` byte copy per frame in flight, to be
+    /// written with `update_buffer_deferred`. Panics if `size` is 0.
+    pub fn create_per_frame_buffer(
+        &self,
+        size: DeviceSize,
+        usage: BufferUsageFlags,
+    ) -> PerFrameBuffer {
+        PerFrameBuffer::new(&self.context, size, usage, self.frames_in_flight)
+    }
+
+    /// Writes `data` to the copy of `buffer` belonging to the frame being
+    /// built, after waiting for the GPU to finish the last frame that read
+    /// that copy, so updating e.g. vertex data every frame cannot corrupt a
+    /// frame still in flight. A copy too small for `data` is replaced, and
+    /// the old one destroyed through the deletion queue once no frame uses
+    /// it. Fails only when the device is lost.
+    pub fn update_buffer_deferred(
+        &mut self,
+        buffer: &mut PerFrameBuffer,
+        data: &[u8],
+    ) -> Result<(), EngineError> {
+        self.wait_for_frame_slot()?;
+        if let Some(old_buffer) = buffer.write(&self.context, self.current_frame, data) {
+            self.deletion_queue
+                .push(self.frames_submitted, move || drop(old_buffer));
+        }
+        Ok(())
+    }
+
+    /// Creates a buffer of `count` 32-bit predicates for `draw_conditional`,
+    /// written by the CPU or, e.g. from occlusion results, by the GPU.
+    pub fn create_predicate_buffer(&self, count: u64) -> MappedBuffer {
+        MappedBuffer::new(
+            &self.context,
+            count * 4,
+            BufferUsageFlags::CONDITIONAL_RENDERING_EXT | BufferUsageFlags::TRANSFER_DST,
+        )
+    }
+
+    /// Whether `draw_conditional` is evaluated on the GPU.
+    pub fn conditional_rendering_supported(&self) -> bool {
+        self.conditional_rendering.is_some()
+    }
+
+    /// Whether `register_texture` is available, i.e. the device supports
+    /// the descriptor indexing features it needs.
+    pub fn bindless_textures_supported(&self) -> bool {
+        self.bindless_textures.is_some()
+    }
+
+    /// Adds a texture to the array bound at set 0, binding 0 of every
+    /// material and returns its index there, for shaders to pick it with a
+    /// per-draw value such as a push constant. The view must be in
+    /// `SHADER_READ_ONLY_OPTIMAL`, and it and `sampler` have to outlive every
+    /// frame using them. Fails with `ErrorKind::Unsupported` without
+    /// descriptor indexing and `ErrorKind::OutOfMemory` once the array is
+    /// full.
+    pub fn register_texture(
+        &mut self,
+        image_view: ImageView,
+        sampler: Sampler,
+    ) -> Result<u32, Error> {
+        let Some(bindless_textures) = self.bindless_textures.as_mut() else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Bindless textures need VK_EXT_descriptor_indexing",
+            ));
+        };
+        bindless_textures
+            .register(image_view, sampler)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::OutOfMemory,
+                    format!(
+                        "All {} bindless texture slots are taken",
+                        bindless_textures.capacity()
+                    ),
+                )
+            })
+    }
+
+    /// Points the uniform set of every draw with uniforms at its buffer,
+    /// with one set per buffer from this frame's allocator.
+    fn allocate_uniform_sets(&mut self, draws: &mut [DrawCall]) {
+        let mut sets = HashMap::new();
+        for uniforms in draws.iter_mut().filter_map(|draw| draw.uniforms.as_mut()) {
+            uniforms.set = *sets
+                .entry((uniforms.buffer, uniforms.range))
+                .or_insert_with(|| {
+                    let set = self.allocate_frame_descriptor_set(self.uniform_set_layout);
+                    let buffer_info = [DescriptorBufferInfo::default()
+                        .buffer(uniforms.buffer)
+                        .range(uniforms.range)];
+                    unsafe {
+                        self.context.device.update_descriptor_sets(
+                            &[WriteDescriptorSet::default()
+                                .dst_set(set)
+                                .dst_binding(0)
+                                .descriptor_type(DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                                .buffer_info(&buffer_info)],
+                            &[],
+                        );
+                    }
+                    set
+                });
+        }
+    }
+
+    /// Allocates a descriptor set that stays valid until this frame slot is
+    /// reused, i.e. for the frame currently being built. Private, since the
+    /// shared pipeline layout can only bind sets of its own layouts.
+    fn allocate_frame_descriptor_set(&mut self, layout: DescriptorSetLayout) -> DescriptorSet {
+        self.frame_descriptor_allocators[self.current_frame].allocate(layout)
+    }
+
+    /// Creates an offscreen target of `extent`. The caller owns it; it keeps
+    /// the device alive and is released on drop.
+    pub fn create_render_target(
+        &self,
+        extent: Extent2D,
+        color_format: Format,
+        depth_format: Option<Format>,
+    ) -> RenderTarget {
+        RenderTarget::new(&self.context, extent, color_format, depth_format)
+    }
+
+    /// Creates a persistently mapped uniform buffer of `size` bytes. Like a
+    /// render target, it is owned by the caller and released on drop.
+    pub fn create_uniform_buffer(&self, size: DeviceSize) -> MappedBuffer {
+        MappedBuffer::new(&self.context, size, BufferUsageFlags::UNIFORM_BUFFER)
+    }
+
+    /// Creates a sampler, e.g. `SamplerDesc::repeat()` for tiled textures or
+    /// `SamplerDesc::clamp_to_edge()` for UI textures. Fails for
+    /// `CLAMP_TO_BORDER` without a border color.
+    pub fn create_sampler(&self, desc: &SamplerDesc) -> Result<SamplerGuard, Error> {
+        if desc.address_mode == SamplerAddressMode::CLAMP_TO_BORDER && desc.border_color.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "CLAMP_TO_BORDER samplers need a border color",
+            ));
+        }
+        Ok(SamplerGuard::new(&self.context, desc))
+    }
+
+    /// Copies the first `count` elements of type `T` out of `buffer`, which
+    /// needs `TRANSFER_SRC` usage. A slow debug helper, e.g. for checking
+    /// compute shader output: it submits a one-off copy after all work queued
+    /// so far and blocks until the GPU has finished it. `T` has to be plain
+    /// data that any bytes the GPU wrote form a valid value of.
+    pub fn read_buffer<T: Copy>(&self, buffer: &Buffer, count: usize) -> Vec<T> {
+        let size = (count * size_of::<T>()) as DeviceSize;
+        assert!(
+            size <= buffer.size(),
+            "Reading {size} bytes exceeds the buffer size of {}",
+            buffer.size()
+        );
+        if size == 0 {
+            return Vec::new();
+        }
+        // Not necessarily coherent, hence the invalidation below.
+        let staging = Buffer::new(
+            &self.context,
+            size,
+            BufferUsageFlags::TRANSFER_DST,
+            MemoryPropertyFlags::HOST_VISIBLE,
+        );
+        self.submit_and_wait(|device, command_buffer| unsafe {
+            // Waits for every earlier submission that may write the buffer.
+            MemoryBarrier::all_writes_to_transfer_read().record(device, command_buffer);
+            device.cmd_copy_buffer(
+                command_buffer,
+                buffer.handle(),
+                staging.handle(),
+                &[BufferCopy::default().size(size)],
+            );
+            MemoryBarrier::transfer_write_to_host_read().record(device, command_buffer);
+        });
+
+        let device = &self.context.device;
+        unsafe {
+            let mapped = device
+                .map_memory(staging.memory(), 0, vk::WHOLE_SIZE, MemoryMapFlags::empty())
+                .expect("Failed to map readback buffer");
+            // The whole mapping from offset 0 always satisfies
+            // nonCoherentAtomSize.
+            device
+                .invalidate_mapped_memory_ranges(&[MappedMemoryRange::default()
+                    .memory(staging.memory())
+                    .size(vk::WHOLE_SIZE)])
+                .expect("Failed to invalidate readback memory");
+            let mut data = Vec::<T>::with_capacity(count);
+            ptr::copy_nonoverlapping(
+                mapped.cast::<u8>(),
+                data.as_mut_ptr().cast::<u8>(),
+                size as usize,
+            );
+            data.set_len(count);
+            device.unmap_memory(staging.memory());
+            data
+        }
+    }
+
+    /// Creates a sampled 2D array texture with one layer per entry of
+    /// `layers`, each tightly packed 8-bit RGBA pixels of `extent`, and
+    /// uploads them, blocking until the copy is done. Shaders pick the layer,
+    /// e.g. from a push constant, so one draw can use many textures. A full
+    /// mip chain is blitted from the uploaded level if the format supports
+    /// linear filtering, otherwise the texture keeps a single level. The
+    /// texture ends up in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn create_texture_array(
+        &self,
+        extent: Extent2D,
+        layers: &[&[u8]],
+    ) -> Result<ResourceImage, Error> {
+        let max_layers = unsafe {
+            self.context
+                .instance
+                .get_physical_device_properties(self.context.physical_device)
+                .limits
+                .max_image_array_layers
+        };
+        if layers.is_empty() || layers.len() > max_layers as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Texture arrays need between 1 and {max_layers} layers, got {}",
+                    layers.len()
+                ),
+            ));
+        }
+        let layer_size = extent.width as usize * extent.height as usize * 4;
+        if let Some(layer) = layers.iter().position(|layer| layer.len() != layer_size) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Layer {layer} has {} bytes instead of {layer_size}",
+                    layers[layer].len()
+                ),
+            ));
+        }
+
+        let format = Format::R8G8B8A8_SRGB;
+        let mip_features = FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+            | FormatFeatureFlags::BLIT_SRC
+            | FormatFeatureFlags::BLIT_DST;
+        let supports_mips = unsafe {
+            self.context
+                .instance
+                .get_physical_device_format_properties(self.context.physical_device, format)
+                .optimal_tiling_features
+                .contains(mip_features)
+        };
+        let mip_levels = if supports_mips {
+            mip_level_count(extent)
+        } else {
+            println!("{format:?} can't be blitted with linear filtering, skipping mipmaps");
+            1
+        };
+        let image = ResourceImage::new_array(
+            &self.context,
+            extent,
+            format,
+            ImageUsageFlags::SAMPLED
+                | ImageUsageFlags::TRANSFER_DST
+                | ImageUsageFlags::TRANSFER_SRC,
+            layers.len() as u32,
+            mip_levels,
+        );
+        let mut staging = MappedBuffer::new(
+            &self.context,
+            (layer_size * layers.len()) as DeviceSize,
+            BufferUsageFlags::TRANSFER_SRC,
+        );
+        let mut regions = Vec::with_capacity(layers.len());
+        for (index, layer) in layers.iter().enumerate() {
+            let offset = (index * layer_size) as DeviceSize;
+            staging.write(offset, layer);
+            regions.push(
+                BufferImageCopy::default()
+                    .buffer_offset(offset)
+                    .image_subresource(
+                        ImageSubresourceLayers::default()
+                            .aspect_mask(ImageAspectFlags::COLOR)
+                            .base_array_layer(index as u32)
+                            .layer_count(1),
+                    )
+                    .image_extent(Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    }),
+            );
+        }
+        self.submit_and_wait(|device, command_buffer| unsafe {
+            ImageBarrier::undefined_to_transfer_dst(image.handle(), ImageAspectFlags::COLOR)
+                .record(device, command_buffer);
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.buffer().handle(),
+                image.handle(),
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+            record_mip_chain(device, command_buffer, &image, extent);
+        });
+        Ok(image)
+    }
+
+    /// Records a one-off command buffer with `record`, submits it to the
+    /// graphics queue and blocks until it has executed. For uploads and
+    /// readbacks outside the frame, not for anything per frame.
+    fn submit_and_wait(&self, record: impl FnOnce(&Device, CommandBuffer)) {
+        let device = &self.context.device;
+        unsafe {
+            let command_buffer = device
+                .allocate_command_buffers(
+                    &CommandBufferAllocateInfo::default()
+                        .command_pool(self.command_pool)
+                        .level(CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(1),
+                )
+                .expect("Failed to allocate one-off command buffer")[0];
+            device
+                .begin_command_buffer(
+                    command_buffer,
+                    &CommandBufferBeginInfo::default()
+                        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .expect("Failed to begin one-off command buffer");
+            record(device, command_buffer);
+            device
+                .end_command_buffer(command_buffer)
+                .expect("Failed to end one-off command buffer");
+
+            let fence = device
+                .create_fence(&FenceCreateInfo::default(), None)
+                .expect("Failed to create one-off fence");
+            let command_buffers = [command_buffer];
+            device
+                .queue_submit(
+                    self.graphics_queue,
+                    &[SubmitInfo::default().command_buffers(&command_buffers)],
+                    fence,
+                )
+                .expect("Failed to submit one-off command buffer");
+            device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .expect("Failed to wait for one-off command buffer");
+            device.destroy_fence(fence, None);
+            device.free_command_buffers(self.command_pool, &command_buffers);
+        }
+    }
+
+    /// Creates a uniform buffer of `len` elements of `element_size` bytes,
+    /// each at an offset usable as a dynamic offset on this device.
+    pub fn create_dynamic_uniform_buffer(
+        &self,
+        element_size: DeviceSize,
+        len: usize,
+    ) -> DynamicUniformBuffer {
+        DynamicUniformBuffer::new(&self.context, element_size, len)
+    }
+
+    /// Creates a pool for placing many small buffers, typically with
+    /// `DEVICE_LOCAL` memory, in a few large allocations.
+    pub fn create_buffer_pool(&self, memory_flags: MemoryPropertyFlags) -> BufferPool {
+        BufferPool::new(&self.context, memory_flags)
+    }
+
+    /// Uploads vertex data, e.g. a mesh's vertices as bytes, into a new
+    /// vertex buffer placed in `pool`, blocking until the copy is done.
+    pub fn upload_vertex_buffer(&self, pool: &mut BufferPool, vertices: &[u8]) -> PooledBuffer {
+        self.upload_to_pool(pool, vertices, BufferUsageFlags::VERTEX_BUFFER)
+    }
+
+    /// Uploads 32-bit indices into a new index buffer placed in `pool`,
+    /// blocking until the copy is done.
+    pub fn upload_index_buffer(&self, pool: &mut BufferPool, indices: &[u32]) -> PooledBuffer {
+        let bytes: Vec<u8> = indices
+            .iter()
+            .flat_map(|index| index.to_ne_bytes())
+            .collect();
+        self.upload_to_pool(pool, &bytes, BufferUsageFlags::INDEX_BUFFER)
+    }
+
+    /// Copies `data` through a staging buffer into a buffer from `pool` with
+    /// `usage`, and makes the copy visible to vertex input.
+    fn upload_to_pool(
+        &self,
+        pool: &mut BufferPool,
+        data: &[u8],
+        usage: BufferUsageFlags,
+    ) -> PooledBuffer {
+        assert!(!data.is_empty(), "Cannot upload an empty buffer");
+        let size = data.len() as DeviceSize;
+        let mut staging = MappedBuffer::new(&self.context, size, BufferUsageFlags::TRANSFER_SRC);
+        staging.write(0, data);
+        let buffer = pool.allocate(size, usage | BufferUsageFlags::TRANSFER_DST);
+        self.submit_and_wait(|device, command_buffer| unsafe {
+            device.cmd_copy_buffer(
+                command_buffer,
+                staging.buffer().handle(),
+                buffer.handle(),
+                &[BufferCopy::default().size(size)],
+            );
+            MemoryBarrier::transfer_write_to_vertex_input().record(device, command_buffer);
+        });
+        buffer
+    }
+
+    /// The first of the combined depth/stencil formats the device can use as
+    /// an optimally tiled attachment, for render targets that need stencil.
+    /// The frame's own stencil attachment has this format too.
+    pub fn depth_stencil_format(&self) -> Option<Format> {
+        self.depth_stencil_format
+    }
+
+    /// Whether the frame has a stencil attachment, which `MaterialDesc::stencil`
+    /// needs.
+    pub fn stencil_supported(&self) -> bool {
+        self.depth_stencil_format.is_some()
+    }
+
+    fn ensure_pipelines(&mut self, draws: &[DrawCall]) {
+        for draw in draws {
+            self.ensure_pipeline(draw.material);
+        }
+    }
+
+    /// Builds the pipeline of `material` unless it exists, building its base
+    /// pipeline first when it is a derivative.
+    fn ensure_pipeline(&mut self, material: MaterialId) {
+        if self.pipelines.contains_key(&material) {
+            return;
+        }
+        let base_material = self.materials[material.index()].desc.derive_from;
+        if let Some(base_material) = base_material {
+            self.ensure_pipeline(base_material);
+        }
+        let allow_derivatives = self
+            .materials
+            .iter()
+            .any(|other| other.desc.derive_from == Some(material));
+
+        let start = Instant::now();
+        let pipeline = create_graphics_pipeline(
+            &self.context,
+            self.pipeline_cache,
+            (self.render_pass, self.depth_stencil_format.is_some()),
+            self.pipeline_layout,
+            &self.materials[material.index()],
+            allow_derivatives,
+            base_material.map(|base_material| self.pipelines[&base_material].handle()),
+        );
+        println!(
+            "Created pipeline for {material:?}{} in {:.2} ms",
+            if base_material.is_some() {
+                " (derivative)"
+            } else {
+                ""
+            },
+            start.elapsed().as_secs_f32() * 1000.0
+        );
+        self.pipelines.insert(material, pipeline);
+    }
+
+    /// Limits `draw_frame` to at most `max_fps` frames per second, on top of
+    /// whatever the present mode enforces. `None` removes the limit.
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        self.frame_timer.set_max_fps(max_fps);
+    }
+
+    /// Blocks until the GPU is done with the frame that last used the
+    /// current frame slot. Returns the slot's fence to signal next, or a
+    /// null fence when frames are paced with the timeline semaphore.
+    fn wait_for_frame_slot(&self) -> Result<Fence, EngineError> {
+        unsafe {
+            match &self.frame_timeline {
+                Some(frame_timeline) => {
+                    self.or_device_lost(
+                        frame_timeline.wait_for_frames(self.frames_completed()),
+                        "wait for frame timeline",
+                    )?;
+                    Ok(Fence::null())
+                }
+                None => {
+                    let in_flight_fence = self.in_flight_fences[self.current_frame];
+                    self.or_device_lost(
+                        self.context
+                            .device
+                            .wait_for_fences(&[in_flight_fence], true, u64::MAX),
+                        "wait for in-flight fence",
+                    )?;
+                    Ok(in_flight_fence)
+                }
+            }
+        }
+    }
+
+    /// Records and presents everything queued since the last frame. Fails
+    /// only when the device is lost.
+    pub fn draw_frame(&mut self) -> Result<(), EngineError> {
+        // A minimized window has a zero-sized surface, which cannot back a swapchain.
+        if self.window_extent.width == 0 || self.window_extent.height == 0 {
+            return Ok(());
+        }
+        if self.swapchain_outdated {
+            self.create_surface_state();
+        }
+
+        let mut draws = std::mem::take(&mut self.pending_draws);
+        sort_draws(&mut draws, |material| {
+            self.materials[material.index()].desc.is_transparent()
+        });
+        self.ensure_pipelines(&draws);
+        self.frame_timer.wait();
+
+        unsafe {
+            let image_available_semaphore = self.image_available_semaphores[self.current_frame];
+            let command_buffer = self.command_buffers[self.current_frame];
+
+            let in_flight_fence = self.wait_for_frame_slot()?;
+            self.deletion_queue.flush(self.frames_completed());
+            self.read_frame_timestamps();
+            self.frame_descriptor_allocators[self.current_frame].reset();
+            self.allocate_uniform_sets(&mut draws);
+
+            let image_index = match self.swapchain_device.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                image_available_semaphore,
+                Fence::null(),
+            ) {
+                Ok((image_index, _suboptimal)) => image_index,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.create_surface_state();
+                    return Ok(());
+                }
+                result => self.or_device_lost(result, "acquire swapchain image")?.0,
+            };
+
+            let render_finished_semaphore = self.render_finished_semaphores[image_index as usize];
+
+            if in_flight_fence != Fence::null() {
+                self.context
+                    .device
+                    .reset_fences(&[in_flight_fence])
+                    .expect("Failed to reset in-flight fence");
+            }
+            self.context
+                .device
+                .reset_command_buffer(command_buffer, CommandBufferResetFlags::empty())
+                .expect("Failed to reset command buffer");
+            self.record_command_buffer(command_buffer, image_index as usize, &draws);
+            self.timestamps_written[self.current_frame] = self.timestamp_query_pool.is_some();
+
+            let wait_semaphores = [image_available_semaphore];
+            let wait_stages = [PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+            let command_buffers = [command_buffer];
+            let mut signal_semaphores = vec![render_finished_semaphore];
+            // Binary semaphores ignore their entry in the value arrays.
+            let wait_values = [0];
+            let mut signal_values = vec![0];
+            self.frames_submitted += 1;
+            if let Some(frame_timeline) = &self.frame_timeline {
+                signal_semaphores.push(frame_timeline.semaphore);
+                signal_values.push(self.frames_submitted);
+            }
+            let mut timeline_submit_info = TimelineSemaphoreSubmitInfo::default()
+                .wait_semaphore_values(&wait_values)
+                .signal_semaphore_values(&signal_values);
+            let mut submit_info = SubmitInfo::default()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores);
+            if self.frame_timeline.is_some() {
+                submit_info = submit_info.push_next(&mut timeline_submit_info);
+            }
+            self.or_device_lost(
+                self.context.device.queue_submit(
+                    self.graphics_queue,
+                    &[submit_info],
+                    in_flight_fence,
+                ),
+                "submit draw command buffer",
+            )?;
+
+            let swapchains = [self.swapchain];
+            let image_indices = [image_index];
+            let present_wait_semaphores = [render_finished_semaphore];
+            let present_info = PresentInfoKHR::default()
+                .wait_semaphores(&present_wait_semaphores)
+                .swapchains(&swapchains)
+                .image_indices(&image_indices);
+            match self
+                .swapchain_device
+                .queue_present(self.present_queue(), &present_info)
+            {
+                Ok(false) => {}
+                Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.swapchain_outdated = true;
+                }
+                result => {
+                    self.or_device_lost(result, "present swapchain image")?;
+                }
+            }
+
+            self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
+        }
+        Ok(())
+    }
+
+    /// Reports `ERROR_DEVICE_LOST` and turns it into `EngineError::DeviceLost`.
+    /// Any other error is a bug and panics as before.
+    fn or_device_lost<T>(&self, result: VkResult<T>, action: &str) -> Result<T, EngineError> {
+        match result {
+            Ok(value) => Ok(value),
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                self.report_device_lost();
+                Err(EngineError::DeviceLost)
+            }
+            Err(err) => panic!("Failed to {action}: {err}"),
+        }
+    }
+
+    /// Prints whatever `VK_EXT_device_fault` knows about why the device was
+    /// lost, when the extension is enabled.
+    fn report_device_lost(&self) {
+        println!("Vulkan device lost");
+        let Some(device_fault) = self.device_fault.as_ref() else {
+            println!("VK_EXT_device_fault is not supported, no fault details available");
+            return;
+        };
+
+        let get_device_fault_info = device_fault.fp().get_device_fault_info_ext;
+        unsafe {
+            let mut counts = DeviceFaultCountsEXT::default();
+            let result = get_device_fault_info(device_fault.device(), &mut counts, ptr::null_mut());
+            if result != vk::Result::SUCCESS {
+                println!("Failed to query device fault counts: {result}");
+                return;
+            }
+
+            let mut address_infos =
+                vec![DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+            let mut vendor_infos =
+                vec![DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+            // The vendor binary is an opaque crash dump, not worth printing.
+            counts.vendor_binary_size = 0;
+            let mut info = DeviceFaultInfoEXT {
+                p_address_infos: address_infos.as_mut_ptr(),
+                p_vendor_infos: vendor_infos.as_mut_ptr(),
+                ..Default::default()
+            };
+            let result = get_device_fault_info(device_fault.device(), &mut counts, &mut info);
+            if result != vk::Result::SUCCESS && result != vk::Result::INCOMPLETE {
+                println!("Failed to query device fault info: {result}");
+                return;
+            }
+
+            println!(
+                "Device fault: {}",
+                info.description_as_c_str()
+                    .map(ffi::CStr::to_string_lossy)
+                    .unwrap_or_default()
+            );
+            for address_info in &address_infos {
+                println!(
+                    "  {:?} at {:#x} (precision {:#x})",
+                    address_info.address_type,
+                    address_info.reported_address,
+                    address_info.address_precision
+                );
+            }
+            for vendor_info in &vendor_infos {
+                println!(
+                    "  vendor fault {:#x} ({:#x}): {}",
+                    vendor_info.vendor_fault_code,
+                    vendor_info.vendor_fault_data,
+                    vendor_info
+                        .description_as_c_str()
+                        .map(ffi::CStr::to_string_lossy)
+                        .unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    fn record_command_buffer(
+        &mut self,
+        command_buffer: CommandBuffer,
+        image_index: usize,
+        draws: &[DrawCall],
+    ) {
+        unsafe {
+            self.context
+                .device
+                .begin_command_buffer(command_buffer, &CommandBufferBeginInfo::default())
+                .expect("Failed to begin command buffer");
+
+            let first_query = (self.current_frame * 2) as u32;
+            if let Some(query_pool) = self.timestamp_query_pool {
+                self.context.device.cmd_reset_query_pool(
+                    command_buffer,
+                    query_pool,
+                    first_query,
+                    2,
+                );
+                self.context.device.cmd_write_timestamp(
+                    command_buffer,
+                    PipelineStageFlags::TOP_OF_PIPE,
+                    query_pool,
+                    first_query,
+                );
+            }
+
+            self.push_debug_label(command_buffer, c"Main render pass", [0.2, 0.4, 0.8, 1.0]);
+            // Clear values are stored as is, without the encoding SRGB
+            // formats apply.
+            let [red, green, blue, alpha] = self.clear_color;
+            let clear_color = if self.shader_encodes_srgb() {
+                [
+                    linear_to_srgb(red),
+                    linear_to_srgb(green),
+                    linear_to_srgb(blue),
+                    alpha,
+                ]
+            } else {
+                self.clear_color
+            };
+            let clear_values = [
+                ClearValue {
+                    color: ClearColorValue {
+                        float32: clear_color,
+                    },
+                },
+                ClearValue {
+                    depth_stencil: ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                },
+            ];
+            // Without a stencil attachment the second value goes unused.
+            let clear_values = &clear_values[..1 + self.depth_stencil_format.is_some() as usize];
+            let render_pass_begin_info = RenderPassBeginInfo::default()
+                .render_pass(self.render_pass)
+                .framebuffer(self.framebuffers[image_index])
+                .render_area(Rect2D {
+                    offset: Offset2D { x: 0, y: 0 },
+                    extent: self.swapchain_extent,
+                })
+                .clear_values(clear_values);
+            let device = &self.context.device;
+            let bindings = FrameBindings {
+                viewport: frame_viewport(self.swapchain_extent, self.flip_viewport_y),
+                pipeline_layout: self.pipeline_layout,
+                texture_set: self.bindless_textures.as_ref().map(BindlessTextures::set),
+            };
+            let pipelines = &self.pipelines;
+            let conditional_rendering = self.conditional_rendering.as_ref();
+            let record = |command_buffer, draws: &[DrawCall]| {
+                record_draws(
+                    device,
+                    conditional_rendering,
+                    command_buffer,
+                    bindings,
+                    pipelines,
+                    draws,
+                )
+            };
+            let record_start = Instant::now();
+            if self
+                .parallel_recording_min_draws
+                .is_some_and(|min_draws| draws.len() >= min_draws)
+            {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    SubpassContents::SECONDARY_COMMAND_BUFFERS,
+                );
+                let secondary_command_buffers = self.parallel_recorder.record(
+                    self.current_frame,
+                    self.render_pass,
+                    self.framebuffers[image_index],
+                    draws,
+                    record,
+                );
+                device.cmd_execute_commands(command_buffer, &secondary_command_buffers);
+            } else {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    SubpassContents::INLINE,
+                );
+                record(command_buffer, draws);
+            }
+            self.last_record_ms = Some(record_start.elapsed().as_secs_f32() * 1000.0);
+
+            self.context.device.cmd_end_render_pass(command_buffer);
+            self.pop_debug_label(command_buffer);
+
+            if let Some(query_pool) = self.timestamp_query_pool {
+                self.context.device.cmd_write_timestamp(
+                    command_buffer,
+                    PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query_pool,
+                    first_query + 1,
+                );
+            }
+
+            self.context
+                .device
+                .end_command_buffer(command_buffer)
+                .expect("Failed to end command buffer");
+        }
+    }
+
+    fn create_instance(
+        window: &Window,
+        entry: &Entry,
+        debug_info: Option<&mut DebugUtilsMessengerCreateInfoEXT>,
+        swapchain_colorspace_enabled: bool,
+    ) -> Result<Instance, Error> {
+        unsafe {
+            let app_info = ApplicationInfo::default()
+                .api_version(vk::API_VERSION_1_1)
+                .engine_name(c"No Engine")
+                .engine_version(1)
+                .application_version(1)
+                .application_name(c"Malbi");
+
+            let raw_display_handle = window
+                .display_handle()
+                .expect("failed to retrieve display handle")
+                .as_raw();
+
+            let enumerate_required_extensions =
+                ash_window::enumerate_required_extensions(raw_display_handle)
+                    .expect("Failed to enumerate required extensions");
+
+            let mut required_extensions = enumerate_required_extensions.to_vec();
+            required_extensions.push(ash::vk::KHR_PORTABILITY_ENUMERATION_NAME.as_ptr());
+            if debug_info.is_some() {
+                required_extensions.push(debug_utils::NAME.as_ptr());
+            }
+            if swapchain_colorspace_enabled {
+                required_extensions.push(swapchain_colorspace::NAME.as_ptr());
+            }
+
+            let validation_layer = [c"VK_LAYER_KHRONOS_validation"];
+
+            let layer_names = validation_layer.map(|layer| layer.as_ptr()).to_vec();
+            let validation_layers_enabled =
+                Self::check_validation_layer_support(entry, &validation_layer);
+
+            let mut instance_create_info = InstanceCreateInfo::default()
+                .application_info(&app_info)
+                .enabled_extension_names(&required_extensions)
+                .flags(InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR);
+
+            if let Some(debug_info) = debug_info {
+                instance_create_info = instance_create_info.push_next(debug_info);
+            }
+
+            if validation_layers_enabled {
+                instance_create_info = instance_create_info.enabled_layer_names(&layer_names);
+                println!("XDD {:?}", instance_create_info.enabled_layer_count);
+            }
+
+            let instance = entry
+                .create_instance(&instance_create_info, None)
+                .expect("Failed to create instance");
+
+            Ok(instance)
+        }
+    }
+
+    fn check_instance_extension_support(entry: &Entry, extension_name: &ffi::CStr) -> bool {
+        unsafe {
+            entry
+                .enumerate_instance_extension_properties(None)
+                .expect("Failed to enumerate instance extension properties")
+                .iter()
+                .any(|extension| extension.extension_name_as_c_str() == Ok(extension_name))
+        }
+    }
+
+    fn check_validation_layer_support(entry: &Entry, used_layer_names: &[&ffi::CStr]) -> bool {
+        unsafe {
+            let layer_properties = entry
+                .enumerate_instance_layer_properties()
+                .expect("Failed to enumerate instance layer properties");
+
+            used_layer_names.iter().all(|name| {
+                layer_properties.iter().any(|layer_property| {
+                    layer_property
+                        .layer_name_as_c_str()
+                        .expect("failed to create layer property")
+                        == *name
+                })
+            })
+        }
+    }
+}
+
+/// Moves transparent draws behind the opaque ones and orders them back to
+/// front, so each blends over what lies behind it. Opaque draws are grouped
+/// by material, so `record_draws` binds each pipeline once. The sort is
+/// stable, which keeps draws of one material, or transparent draws at the
+/// same distance, in submission order.
+fn sort_draws(draws: &mut [DrawCall], is_transparent: impl Fn(MaterialId) -> bool) {
+    draws.sort_by(|a, b| {
+        let a_transparent = is_transparent(a.material);
+        let b_transparent = is_transparent(b.material);
+        a_transparent.cmp(&b_transparent).then_with(|| {
+            if a_transparent {
+                b.camera_distance.total_cmp(&a.camera_distance)
+            } else {
+                a.material.index().cmp(&b.material.index())
+            }
+        })
+    });
+}
+
+/// A viewport covering `extent`. Flipped, it starts at the bottom edge with
+/// a negative height, so clip space Y points up.
+fn frame_viewport(extent: Extent2D, flip_y: bool) -> Viewport {
+    let (y, height) = if flip_y {
+        (extent.height as f32, -(extent.height as f32))
+    } else {
+        (0.0, extent.height as f32)
+    };
+    Viewport {
+        x: 0.0,
+        y,
+        width: extent.width as f32,
+        height,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }
+}
+
+/// State shared by every draw of a frame.
+#[derive(Clone, Copy)]
+struct FrameBindings {
+    viewport: Viewport,
+    pipeline_layout: PipelineLayout,
+    /// Bound to set 0 when bindless textures are supported.
+    texture_set: Option<DescriptorSet>,
+}
+
+/// Sets the viewport, a scissor covering the same area and the texture set,
+/// then records `draws`, rebinding the pipeline only when the material
+/// changes and the uniform set for draws with uniforms.
+fn record_draws(
+    device: &Device,
+    conditional_rendering: Option<&conditional_rendering::Device>,
+    command_buffer: CommandBuffer,
+    bindings: FrameBindings,
+    pipelines: &HashMap<MaterialId, PipelineGuard>,
+    draws: &[DrawCall],
+) {
+    let FrameBindings {
+        viewport,
+        pipeline_layout,
+        texture_set,
+    } = bindings;
+    unsafe {
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(
+            command_buffer,
+            0,
+            &[Rect2D {
+                offset: Offset2D { x: 0, y: 0 },
+                extent: Extent2D {
+                    width: viewport.width as u32,
+                    height: viewport.height.abs() as u32,
+                },
+            }],
+        );
+        // Stays bound across pipeline changes, since every pipeline shares
+        // the layout.
+        if let Some(texture_set) = texture_set {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[texture_set],
+                &[],
+            );
+        }
+        let mut bound_material = None;
+        for draw in draws {
+            if bound_material != Some(draw.material) {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    PipelineBindPoint::GRAPHICS,
+                    pipelines[&draw.material].handle(),
+                );
+                bound_material = Some(draw.material);
+            }
+            if !draw.push_constants.is_empty() {
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipeline_layout,
+                    PUSH_CONSTANT_STAGES,
+                    0,
+                    &draw.push_constants,
+                );
+            }
+            if let Some(depth_bias) = draw.depth_bias {
+                device.cmd_set_depth_bias(
+                    command_buffer,
+                    depth_bias.constant_factor,
+                    depth_bias.clamp,
+                    depth_bias.slope_factor,
+                );
+            }
+            if let Some(line_width) = draw.line_width {
+                device.cmd_set_line_width(command_buffer, line_width);
+            }
+            if let Some(uniforms) = draw.uniforms {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    UNIFORM_SET,
+                    &[uniforms.set],
+                    &[uniforms.dynamic_offset],
+                );
+            }
+            let predicate = draw.predicate.zip(conditional_rendering);
+            if let Some(((buffer, offset), conditional_rendering)) = predicate {
+                // ash has no wrappers for this extension's commands.
+                (conditional_rendering
+                    .fp()
+                    .cmd_begin_conditional_rendering_ext)(
+                    command_buffer,
+                    &ConditionalRenderingBeginInfoEXT::default()
+                        .buffer(buffer)
+                        .offset(offset),
+                );
+            }
+            device.cmd_draw(command_buffer, draw.vertex_count, 1, 0, 0);
+            if let Some((_, conditional_rendering)) = predicate {
+                (conditional_rendering.fp().cmd_end_conditional_rendering_ext)(command_buffer);
+            }
+        }
+    }
+}
+
+/// Fills every mip level of `image` below the first by blitting each level
+/// from the one above it with linear filtering, for all layers at once. All
+/// levels must be in `TRANSFER_DST_OPTIMAL` and end up in
+/// `SHADER_READ_ONLY_OPTIMAL`. With a single level this is only the final
+/// transition.
+fn record_mip_chain(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    image: &ResourceImage,
+    extent: Extent2D,
+) {
+    let handle = image.handle();
+    let layers = ImageSubresourceLayers::default()
+        .aspect_mask(ImageAspectFlags::COLOR)
+        .layer_count(image.array_layers());
+    let level_corner = |level: u32| Offset3D {
+        x: (extent.width >> level).max(1) as i32,
+        y: (extent.height >> level).max(1) as i32,
+        z: 1,
+    };
+    for level in 1..image.mip_levels() {
+        ImageBarrier::transfer_dst_to_transfer_src(handle, ImageAspectFlags::COLOR)
+            .mip_levels(level - 1, 1)
+            .record(device, command_buffer);
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                handle,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                handle,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[ImageBlit::default()
+                    .src_subresource(layers.mip_level(level - 1))
+                    .src_offsets([Offset3D::default(), level_corner(level - 1)])
+                    .dst_subresource(layers.mip_level(level))
+                    .dst_offsets([Offset3D::default(), level_corner(level)])],
+                Filter::LINEAR,
+            );
+        }
+        ImageBarrier::transfer_src_to_shader_read(handle, ImageAspectFlags::COLOR)
+            .mip_levels(level - 1, 1)
+            .record(device, command_buffer);
+    }
+    ImageBarrier::transfer_dst_to_shader_read(handle, ImageAspectFlags::COLOR)
+        .mip_levels(image.mip_levels() - 1, 1)
+        .record(device, command_buffer);
+}
+
+/// Creates a logical device on the best suitable physical device, ranked by
+/// `device_type_score` and then device local memory, moving on to the next
+/// candidate when device creation fails there. Also returns the name of the
+/// device that worked.
+fn create_device(
+    instance: &Instance,
+    surface_instance: &surface::Instance,
+    surface: SurfaceKHR,
+    queue_flag: QueueFlags,
+    hdr: bool,
+) -> Result<(PhysicalDevice, Device, QueueFamilyIndices, String), EngineError> {
+    unsafe {
+        let enumerated_physical_devices = instance
+            .enumerate_physical_devices()
+            .expect("Failed to enumerate physical devices");
+        let mut candidates = Vec::new();
+        // Reported when no device qualifies, so a device that was only
+        // missing presentation support explains itself.
+        let mut rejection = EngineError::NoSuitableDevice;
+        let requested_index = requested_device_index();
+        for (index, physical_device) in enumerated_physical_devices.into_iter().enumerate() {
+            if requested_index.is_some_and(|requested_index| requested_index != index) {
+                continue;
+            }
+            if !physical_device_suitability(instance, physical_device, queue_flag) {
+                if requested_index.is_some() {
+                    println!("Device {index} selected through {DEVICE_INDEX_VAR} is not suitable");
+                }
+                continue;
+            }
+            match find_queue_family_indices(
+                instance,
+                surface_instance,
+                physical_device,
+                surface,
+                queue_flag,
+            ) {
+                Ok(indices) => candidates.push((physical_device, indices)),
+                Err(err) => rejection = err,
+            }
+        }
+
+        // Stable, so equal candidates keep the enumeration order.
+        candidates.sort_by_key(|&(physical_device, _)| {
+            let device_type = instance
+                .get_physical_device_properties(physical_device)
+                .device_type;
+            Reverse((
+                device_type_score(device_type),
+                device_local_memory(instance, physical_device),
+            ))
+        });
+        for (physical_device, queue_family_indices) in candidates {
+            let device_name = instance
+                .get_physical_device_properties(physical_device)
+                .device_name_as_c_str()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            match create_logical_device(instance, physical_device, queue_family_indices, hdr) {
+                Ok(device) => {
+                    return Ok((physical_device, device, queue_family_indices, device_name))
+                }
+                Err(err) => {
+                    println!(
+                        "Failed to create a device on {device_name} ({err}), trying the next one"
+                    );
+                    rejection = EngineError::DeviceCreationFailed(err);
+                }
+            }
+        }
+        Err(rejection)
+    }
+}
+
+fn create_logical_device(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    queue_family_indices: QueueFamilyIndices,
+    hdr: bool,
+) -> VkResult<Device> {
+    unsafe {
+        let queue_priorities = [1.0];
+        let mut queue_create_info = vec![DeviceQueueCreateInfo::default()
+            .queue_family_index(queue_family_indices.graphics_family)
+            .queue_priorities(&queue_priorities)];
+        if !queue_family_indices.is_shared() {
+            queue_create_info.push(
+                DeviceQueueCreateInfo::default()
+                    .queue_family_index(queue_family_indices.presentation_family)
+                    .queue_priorities(&queue_priorities),
+            );
+        }
+
+        let mut device_extensions = vec![swapchain::NAME.as_ptr()];
+        if supports_memory_budget(instance, physical_device) {
+            device_extensions.push(memory_budget::NAME.as_ptr());
+        }
+        let timeline_semaphore_supported = supports_timeline_semaphore(instance, physical_device);
+        if timeline_semaphore_supported {
+            device_extensions.push(timeline_semaphore::NAME.as_ptr());
+        }
+        if hdr && check_device_extension_support(instance, physical_device, hdr_metadata::NAME) {
+            device_extensions.push(hdr_metadata::NAME.as_ptr());
+        }
+        let device_fault_supported = supports_device_fault(instance, physical_device);
+        if device_fault_supported {
+            device_extensions.push(device_fault::NAME.as_ptr());
+        }
+        let conditional_rendering_supported =
+            supports_conditional_rendering(instance, physical_device);
+        if conditional_rendering_supported {
+            device_extensions.push(conditional_rendering::NAME.as_ptr());
+        }
+        let descriptor_indexing_supported = supports_descriptor_indexing(instance, physical_device);
+        if descriptor_indexing_supported {
+            device_extensions.push(descriptor_indexing::NAME.as_ptr());
+            device_extensions.push(maintenance3::NAME.as_ptr());
+        }
+        let physical_devices_feature = instance.get_physical_device_features(physical_device);
+        let mut timeline_semaphore_features =
+            PhysicalDeviceTimelineSemaphoreFeatures::default().timeline_semaphore(true);
+        let mut device_create_info = DeviceCreateInfo::default()
+            .enabled_features(&physical_devices_feature)
+            .enabled_extension_names(&device_extensions)
+            .queue_create_infos(&queue_create_info);
+        if timeline_semaphore_supported {
+            device_create_info = device_create_info.push_next(&mut timeline_semaphore_features);
+        }
+        let mut device_fault_features =
+            PhysicalDeviceFaultFeaturesEXT::default().device_fault(true);
+        if device_fault_supported {
+            device_create_info = device_create_info.push_next(&mut device_fault_features);
+        }
+        let mut conditional_rendering_features =
+            PhysicalDeviceConditionalRenderingFeaturesEXT::default().conditional_rendering(true);
+        if conditional_rendering_supported {
+            device_create_info = device_create_info.push_next(&mut conditional_rendering_features);
+        }
+        let mut descriptor_indexing_features = bindless_texture_features();
+        if descriptor_indexing_supported {
+            device_create_info = device_create_info.push_next(&mut descriptor_indexing_features);
+        }
+
+        instance.create_device(physical_device, &device_create_info, None)
+    }
+}
+
+fn check_device_extension_support(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    extension_name: &ffi::CStr,
+) -> bool {
+    unsafe {
+        instance
+            .enumerate_device_extension_properties(physical_device)
+            .expect("Failed to enumerate device extension properties")
+            .iter()
+            .any(|extension| extension.extension_name_as_c_str() == Ok(extension_name))
+    }
+}
+
+/// `VK_EXT_device_fault` is only useful with its `deviceFault` feature, read
+/// through `vkGetPhysicalDeviceFeatures2` on a Vulkan 1.1 device.
+fn supports_device_fault(instance: &Instance, physical_device: PhysicalDevice) -> bool {
+    unsafe {
+        if instance
+            .get_physical_device_properties(physical_device)
+            .api_version
+            < vk::API_VERSION_1_1
+            || !check_device_extension_support(instance, physical_device, device_fault::NAME)
+        {
+            return false;
+        }
+        let mut device_fault_features = PhysicalDeviceFaultFeaturesEXT::default();
+        let mut features = PhysicalDeviceFeatures2::default().push_next(&mut device_fault_features);
+        instance.get_physical_device_features2(physical_device, &mut features);
+        device_fault_features.device_fault == vk::TRUE
+    }
+}
+
+/// The descriptor indexing features `BindlessTextures` relies on.
+fn bindless_texture_features() -> PhysicalDeviceDescriptorIndexingFeatures<'static> {
+    PhysicalDeviceDescriptorIndexingFeatures::default()
+        .runtime_descriptor_array(true)
+        .shader_sampled_image_array_non_uniform_indexing(true)
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_variable_descriptor_count(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+}
+
+/// `VK_EXT_descriptor_indexing` and the `VK_KHR_maintenance3` it depends on,
+/// with every feature in `bindless_texture_features`.
+fn supports_descriptor_indexing(instance: &Instance, physical_device: PhysicalDevice) -> bool {
+    unsafe {
+        if instance
+            .get_physical_device_properties(physical_device)
+            .api_version
+            < vk::API_VERSION_1_1
+            || !check_device_extension_support(instance, physical_device, descriptor_indexing::NAME)
+            || !check_device_extension_support(instance, physical_device, maintenance3::NAME)
+        {
+            return false;
+        }
+        let mut supported = PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut features = PhysicalDeviceFeatures2::default().push_next(&mut supported);
+        instance.get_physical_device_features2(physical_device, &mut features);
+        let required = bindless_texture_features();
+        [
+            (
+                required.runtime_descriptor_array,
+                supported.runtime_descriptor_array,
+            ),
+            (
+                required.shader_sampled_image_array_non_uniform_indexing,
+                supported.shader_sampled_image_array_non_uniform_indexing,
+            ),
+            (
+                required.descriptor_binding_partially_bound,
+                supported.descriptor_binding_partially_bound,
+            ),
+            (
+                required.descriptor_binding_variable_descriptor_count,
+                supported.descriptor_binding_variable_descriptor_count,
+            ),
+            (
+                required.descriptor_binding_sampled_image_update_after_bind,
+                supported.descriptor_binding_sampled_image_update_after_bind,
+            ),
+        ]
+        .iter()
+        .all(|&(required, supported)| required == vk::FALSE || supported == vk::TRUE)
+    }
+}
+
+/// `VK_EXT_conditional_rendering` needs both the extension and its feature
+/// bit, which is read through `vkGetPhysicalDeviceFeatures2`.
+fn supports_conditional_rendering(instance: &Instance, physical_device: PhysicalDevice) -> bool {
+    unsafe {
+        if instance
+            .get_physical_device_properties(physical_device)
+            .api_version
+            < vk::API_VERSION_1_1
+            || !check_device_extension_support(
+                instance,
+                physical_device,
+                conditional_rendering::NAME,
+            )
+        {
+            return false;
+        }
+        let mut conditional_rendering_features =
+            PhysicalDeviceConditionalRenderingFeaturesEXT::default();
+        let mut features =
+            PhysicalDeviceFeatures2::default().push_next(&mut conditional_rendering_features);
+        instance.get_physical_device_features2(physical_device, &mut features);
+        conditional_rendering_features.conditional_rendering == vk::TRUE
+    }
+}
+
+/// `VK_KHR_timeline_semaphore` needs both the extension and its feature bit,
+/// which is read through `vkGetPhysicalDeviceFeatures2` on a Vulkan 1.1 device.
+fn supports_timeline_semaphore(instance: &Instance, physical_device: PhysicalDevice) -> bool {
+    unsafe {
+        if instance
+            .get_physical_device_properties(physical_device)
+            .api_version
+            < vk::API_VERSION_1_1
+            || !check_device_extension_support(instance, physical_device, timeline_semaphore::NAME)
+        {
+            return false;
+        }
+        let mut timeline_semaphore_features = PhysicalDeviceTimelineSemaphoreFeatures::default();
+        let mut features =
+            PhysicalDeviceFeatures2::default().push_next(&mut timeline_semaphore_features);
+        instance.get_physical_device_features2(physical_device, &mut features);
+        timeline_semaphore_features.timeline_semaphore == vk::TRUE
+    }
+}
+
+/// `VK_EXT_memory_budget` is queried through `vkGetPhysicalDeviceMemoryProperties2`,
+/// which additionally requires a Vulkan 1.1 device.
+fn supports_memory_budget(instance: &Instance, physical_device: PhysicalDevice) -> bool {
+    unsafe {
+        instance
+            .get_physical_device_properties(physical_device)
+            .api_version
+            >= vk::API_VERSION_1_1
+            && check_device_extension_support(instance, physical_device, memory_budget::NAME)
+    }
+}
+
+fn physical_device_suitability(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    queue_flag: QueueFlags,
+) -> bool {
+    find_queue_family_index(instance, &physical_device, queue_flag).is_some()
+}
+
+/// Preference of a device type when several devices are suitable: discrete
+/// GPUs first, software rasterizers last.
+fn device_type_score(device_type: PhysicalDeviceType) -> u32 {
+    match device_type {
+        PhysicalDeviceType::DISCRETE_GPU => 4,
+        PhysicalDeviceType::INTEGRATED_GPU => 3,
+        PhysicalDeviceType::VIRTUAL_GPU => 2,
+        PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    }
+}
+
+fn find_queue_family_index(
+    instance: &Instance,
+    physical_device: &PhysicalDevice,
+    queue_flag: QueueFlags,
+) -> Option<usize> {
+    unsafe {
+        instance
+            .get_physical_device_queue_family_properties(*physical_device)
+            .iter()
+            .enumerate()
+            .find_map(|(idx, queue_property)| {
+                if queue_property.queue_flags.contains(queue_flag) {
+                    Some(idx)
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+/// Picks the graphics family via `find_queue_family_index` and a family that
+/// can present to `surface`, preferring the graphics family itself so the
+/// common case needs only one queue. Fails with `NoPresentQueue` when no
+/// family at all can present to `surface`.
+fn find_queue_family_indices(
+    instance: &Instance,
+    surface_instance: &surface::Instance,
+    physical_device: PhysicalDevice,
+    surface: SurfaceKHR,
+    queue_flag: QueueFlags,
+) -> Result<QueueFamilyIndices, EngineError> {
+    unsafe {
+        let families: Vec<_> = instance
+            .get_physical_device_queue_family_properties(physical_device)
+            .iter()
+            .enumerate()
+            .map(|(family, properties)| {
+                let present_supported = surface_instance
+                    .get_physical_device_surface_support(physical_device, family as u32, surface)
+                    .unwrap_or(false);
+                (properties.queue_flags, present_supported)
+            })
+            .collect();
+        pick_queue_families(&families, queue_flag)
+    }
+}
+
+/// Picks the first family with `queue_flag` for graphics, and presents from
+/// it too when it can, otherwise from the first family that can. `families`
+/// holds the flags and surface support of each family, by index.
+fn pick_queue_families(
+    families: &[(QueueFlags, bool)],
+    queue_flag: QueueFlags,
+) -> Result<QueueFamilyIndices, EngineError> {
+    let graphics_family = families
+        .iter()
+        .position(|&(flags, _)| flags.contains(queue_flag))
+        .ok_or(EngineError::NoSuitableDevice)?;
+    let presentation_family = if families[graphics_family].1 {
+        graphics_family
+    } else {
+        families
+            .iter()
+            .position(|&(_, present_supported)| present_supported)
+            .ok_or(EngineError::NoPresentQueue)?
+    };
+
+    Ok(QueueFamilyIndices {
+        graphics_family: graphics_family as u32,
+        presentation_family: presentation_family as u32,
+    })
+}
+
+/// The first of `DEPTH_STENCIL_FORMATS` the device can use as an optimally
+/// tiled attachment.
+fn find_depth_stencil_format(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+) -> Option<Format> {
+    DEPTH_STENCIL_FORMATS.into_iter().find(|&format| unsafe {
+        instance
+            .get_physical_device_format_properties(physical_device, format)
+            .optimal_tiling_features
+            .contains(FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    })
+}
+
+/// Layout of `UNIFORM_SET`: a single dynamic uniform buffer, visible to the
+/// same stages as the push constants.
+fn create_uniform_set_layout(device: &Device) -> DescriptorSetLayout {
+    let bindings = [DescriptorSetLayoutBinding::default()
+        .binding(0)
+        .descriptor_type(DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+        .descriptor_count(1)
+        .stage_flags(PUSH_CONSTANT_STAGES)];
+    unsafe {
+        device
+            .create_descriptor_set_layout(
+                &DescriptorSetLayoutCreateInfo::default().bindings(&bindings),
+                None,
+            )
+            .expect("Failed to create uniform descriptor set layout")
+    }
+}
+
+/// Creates the render pass drawing into swapchain images of `format`, and the
+/// pipeline layout every material shares.
+fn create_pipeline_state(
+    device: &Device,
+    format: Format,
+    depth_stencil_format: Option<Format>,
+    set_layouts: &[DescriptorSetLayout],
+) -> (RenderPass, PipelineLayout) {
+    let render_pass = create_render_pass(device, format, depth_stencil_format);
+    let push_constant_ranges = [PushConstantRange {
+        stage_flags: PUSH_CONSTANT_STAGES,
+        offset: 0,
+        size: PUSH_CONSTANT_SIZE as u32,
+    }];
+    let pipeline_layout = unsafe {
+        device
+            .create_pipeline_layout(
+                &PipelineLayoutCreateInfo::default()
+                    .set_layouts(set_layouts)
+                    .push_constant_ranges(&push_constant_ranges),
+                None,
+            )
+            .expect("Failed to create pipeline layout")
+    };
+    (render_pass, pipeline_layout)
+}
+
+/// Creates a pool holding a start/end timestamp pair per frame in flight,
+/// along with the mask of valid timestamp bits for `queue_family`. Returns
+/// `None` when that family does not support timestamps.
+fn create_timestamp_query_pool(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    device: &Device,
+    queue_family: u32,
+    frames_in_flight: usize,
+) -> (Option<QueryPool>, u64) {
+    unsafe {
+        let valid_bits = instance.get_physical_device_queue_family_properties(physical_device)
+            [queue_family as usize]
+            .timestamp_valid_bits;
+        if valid_bits == 0 {
+            return (None, 0);
+        }
+        let mask = if valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << valid_bits) - 1
+        };
+
+        let query_pool = device
+            .create_query_pool(
+                &QueryPoolCreateInfo::default()
+                    .query_type(QueryType::TIMESTAMP)
+                    .query_count((frames_in_flight * 2) as u32),
+                None,
+            )
+            .expect("Failed to create timestamp query pool");
+        (Some(query_pool), mask)
+    }
+}
+
+/// Creates the pipeline cache, seeding it from `PIPELINE_CACHE_PATH` when the
+/// file exists and was written by this exact device and driver. Missing,
+/// truncated or foreign cache data falls back to an empty cache.
+fn create_pipeline_cache(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    device: &Device,
+) -> PipelineCache {
+    unsafe {
+        let initial_data = fs::read(PIPELINE_CACHE_PATH).unwrap_or_default();
+        let properties = instance.get_physical_device_properties(physical_device);
+        let initial_data = if is_pipeline_cache_compatible(&initial_data, &properties) {
+            initial_data
+        } else {
+            if !initial_data.is_empty() {
+                println!("Ignoring incompatible pipeline cache at {PIPELINE_CACHE_PATH}");
+            }
+            Vec::new()
+        };
+
+        device
+            .create_pipeline_cache(
+                &PipelineCacheCreateInfo::default().initial_data(&initial_data),
+                None,
+            )
+            .or_else(|_| device.create_pipeline_cache(&PipelineCacheCreateInfo::default(), None))
+            .expect("Failed to create pipeline cache")
+    }
+}
+
+fn is_pipeline_cache_compatible(data: &[u8], properties: &PhysicalDeviceProperties) -> bool {
+    if data.len() < PIPELINE_CACHE_HEADER_SIZE {
+        return false;
+    }
+    let read_u32 = |offset: usize| {
+        u32::from_ne_bytes(
+            data[offset..offset + 4]
+                .try_into()
+                .expect("slice is four bytes long"),
+        )
+    };
+
+    read_u32(0) as usize == PIPELINE_CACHE_HEADER_SIZE
+        && read_u32(4) == PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && read_u32(8) == properties.vendor_id
+        && read_u32(12) == properties.device_id
+        && data[16..PIPELINE_CACHE_HEADER_SIZE] == properties.pipeline_cache_uuid
+}
+
+fn query_swapchain_support_details(
+    surface_instance: &surface::Instance,
+    physical_device: PhysicalDevice,
+    surface: SurfaceKHR,
+) -> SwapchainSupportDetails {
+    unsafe {
+        SwapchainSupportDetails {
+            capabilities: surface_instance
+                .get_physical_device_surface_capabilities(physical_device, surface)
+                .expect("Failed to query surface capabilities"),
+            formats: surface_instance
+                .get_physical_device_surface_formats(physical_device, surface)
+                .expect("Failed to query surface formats"),
+            present_modes: surface_instance
+                .get_physical_device_surface_present_modes(physical_device, surface)
+                .expect("Failed to query surface present modes"),
+        }
+    }
+}
+
+fn create_swapchain(
+    swapchain_device: &swapchain::Device,
+    support_details: &SwapchainSupportDetails,
+    surface: SurfaceKHR,
+    settings: SwapchainSettings,
+    queue_family_indices: QueueFamilyIndices,
+    window_extent: Extent2D,
+    old_swapchain: SwapchainKHR,
+) -> (SwapchainKHR, Extent2D) {
+    unsafe {
+        let capabilities = support_details.capabilities;
+        let surface_format = settings.surface_format;
+
+        let extent = if capabilities.current_extent.width != u32::MAX {
+            capabilities.current_extent
+        } else {
+            Extent2D {
+                width: window_extent.width.clamp(
+                    capabilities.min_image_extent.width,
+                    capabilities.max_image_extent.width,
+                ),
+                height: window_extent.height.clamp(
+                    capabilities.min_image_extent.height,
+                    capabilities.max_image_extent.height,
+                ),
+            }
+        };
+
+        let image_count = desired_image_count(&capabilities, settings.present_mode);
+
+        // Images written by the graphics queue and presented from another
+        // family are shared concurrently to avoid ownership transfers.
+        let shared_family_indices = [
+            queue_family_indices.graphics_family,
+            queue_family_indices.presentation_family,
+        ];
+        let mut swapchain_create_info = SwapchainCreateInfoKHR::default()
+            .surface(surface)
+            .min_image_count(image_count)
+            .image_format(surface_format.format)
+            .image_color_space(surface_format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(settings.image_usage)
+            .image_sharing_mode(SharingMode::EXCLUSIVE)
+            .pre_transform(capabilities.current_transform)
+            .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(settings.present_mode)
+            .clipped(true)
+            .old_swapchain(old_swapchain);
+        if !queue_family_indices.is_shared() {
+            swapchain_create_info = swapchain_create_info
+                .image_sharing_mode(SharingMode::CONCURRENT)
+                .queue_family_indices(&shared_family_indices);
+        }
+
+        let swapchain = swapchain_device
+            .create_swapchain(&swapchain_create_info, None)
+            .expect("Failed to create swapchain");
+
+        (swapchain, extent)
+    }
+}
+
+/// `requested` plus `COLOR_ATTACHMENT`, which every surface supports, minus
+/// what `supported` lacks.
+fn supported_image_usage(
+    requested: ImageUsageFlags,
+    supported: ImageUsageFlags,
+) -> ImageUsageFlags {
+    let requested = requested | ImageUsageFlags::COLOR_ATTACHMENT;
+    let unsupported = requested & !supported;
+    if !unsupported.is_empty() {
+        println!("The surface does not support swapchain usage {unsupported:?}, leaving it out");
+    }
+    requested & supported
+}
+
+/// MAILBOX only avoids blocking with a third image to render into while one
+/// is shown and one queued, and FIFO is double buffered with two. One more
+/// than the minimum keeps drivers that need several images for themselves
+/// from stalling. Clamped to what the surface allows.
+fn desired_image_count(capabilities: &SurfaceCapabilitiesKHR, present_mode: PresentModeKHR) -> u32 {
+    let wanted = match present_mode {
+        PresentModeKHR::MAILBOX => 3,
+        _ => 2,
+    };
+    let image_count = wanted.max(capabilities.min_image_count + 1);
+    if capabilities.max_image_count > 0 {
+        image_count.min(capabilities.max_image_count)
+    } else {
+        image_count
+    }
+}
+
+/// Prefers MAILBOX, which never tears and keeps latency low, over FIFO, the
+/// only mode every device supports.
+fn choose_present_mode(present_modes: &[PresentModeKHR]) -> PresentModeKHR {
+    if present_modes.contains(&PresentModeKHR::MAILBOX) {
+        PresentModeKHR::MAILBOX
+    } else {
+        PresentModeKHR::FIFO
+    }
+}
+
+fn is_srgb_format(format: Format) -> bool {
+    matches!(
+        format,
+        Format::B8G8R8A8_SRGB | Format::R8G8B8A8_SRGB | Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+/// The sRGB transfer function, for a linear value in `0.0..=1.0`.
+fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Prefers 8-bit SRGB, or 8-bit UNORM when `unorm` is set, or a 10-bit HDR10
+/// format when `hdr` is set and the surface offers one. `None` only if the
+/// surface reports no formats.
+fn choose_surface_format(
+    formats: &[SurfaceFormatKHR],
+    hdr: bool,
+    unorm: bool,
+) -> Option<SurfaceFormatKHR> {
+    let find = |format, color_space| {
+        formats
+            .iter()
+            .find(|surface_format| {
+                surface_format.format == format && surface_format.color_space == color_space
+            })
+            .copied()
+    };
+
+    let hdr_format = if hdr {
+        let hdr_format = find(
+            Format::A2B10G10R10_UNORM_PACK32,
+            ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        if hdr_format.is_none() {
+            println!("The surface offers no HDR10 format, falling back to SRGB");
+        }
+        hdr_format
+    } else {
+        None
+    };
+    hdr_format
+        .or_else(|| {
+            if unorm {
+                find(Format::B8G8R8A8_UNORM, ColorSpaceKHR::SRGB_NONLINEAR)
+            } else {
+                find(Format::B8G8R8A8_SRGB, ColorSpaceKHR::SRGB_NONLINEAR)
+            }
+        })
+        .or(formats.first().copied())
+}
+
+/// Describes the content as BT.2020 mastered for a 1000 nit display, the
+/// common HDR10 baseline. Without it, displays guess the tone mapping.
+fn set_hdr_metadata(
+    hdr_metadata: &hdr_metadata::Device,
+    swapchain: SwapchainKHR,
+    surface_format: SurfaceFormatKHR,
+) {
+    if surface_format.color_space != ColorSpaceKHR::HDR10_ST2084_EXT {
+        return;
+    }
+    let metadata = HdrMetadataEXT::default()
+        .display_primary_red(XYColorEXT { x: 0.708, y: 0.292 })
+        .display_primary_green(XYColorEXT { x: 0.170, y: 0.797 })
+        .display_primary_blue(XYColorEXT { x: 0.131, y: 0.046 })
+        .white_point(XYColorEXT {
+            x: 0.3127,
+            y: 0.3290,
+        })
+        .max_luminance(1000.0)
+        .min_luminance(0.001)
+        .max_content_light_level(1000.0)
+        .max_frame_average_light_level(400.0);
+    unsafe {
+        hdr_metadata.set_hdr_metadata(&[swapchain], &[metadata]);
+    }
+}
+
+fn create_semaphores(device: &Device, count: usize) -> Vec<Semaphore> {
+    (0..count)
+        .map(|_| unsafe {
+            device
+                .create_semaphore(&SemaphoreCreateInfo::default(), None)
+                .expect("Failed to create semaphore")
+        })
+        .collect()
+}
+
+fn create_image_views(device: &Device, images: &[Image], format: Format) -> Vec<ImageView> {
+    unsafe {
+        images
+            .iter()
+            .map(|&image| {
+                let image_view_create_info = ImageViewCreateInfo::default()
+                    .image(image)
+                    .view_type(ImageViewType::TYPE_2D)
+                    .format(format)
+                    .components(ComponentMapping::default())
+                    .subresource_range(
+                        ImageSubresourceRange::default()
+                            .aspect_mask(ImageAspectFlags::COLOR)
+                            .base_mip_level(0)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(1),
+                    );
+                device
+                    .create_image_view(&image_view_create_info, None)
+                    .expect("Failed to create image view")
+            })
+            .collect()
+    }
+}
+
+/// The frame's render pass: the swapchain image, plus a depth/stencil
+/// attachment cleared every frame when `depth_stencil_format` is given.
+fn create_render_pass(
+    device: &Device,
+    format: Format,
+    depth_stencil_format: Option<Format>,
+) -> RenderPass {
+    unsafe {
+        let mut attachments = vec![AttachmentDescription::default()
+            .format(format)
+            .samples(SampleCountFlags::TYPE_1)
+            .load_op(AttachmentLoadOp::CLEAR)
+            .store_op(AttachmentStoreOp::STORE)
+            .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+            .initial_layout(ImageLayout::UNDEFINED)
+            .final_layout(ImageLayout::PRESENT_SRC_KHR)];
+        if let Some(depth_stencil_format) = depth_stencil_format {
+            attachments.push(
+                AttachmentDescription::default()
+                    .format(depth_stencil_format)
+                    .samples(SampleCountFlags::TYPE_1)
+                    .load_op(AttachmentLoadOp::CLEAR)
+                    .store_op(AttachmentStoreOp::DONT_CARE)
+                    .stencil_load_op(AttachmentLoadOp::CLEAR)
+                    .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(ImageLayout::UNDEFINED)
+                    .final_layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+            );
+        }
+        let color_attachment_refs = [AttachmentReference::default()
+            .attachment(0)
+            .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let depth_stencil_attachment_ref = AttachmentReference::default()
+            .attachment(1)
+            .layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        let mut subpass = SubpassDescription::default()
+            .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs);
+        if depth_stencil_format.is_some() {
+            subpass = subpass.depth_stencil_attachment(&depth_stencil_attachment_ref);
+        }
+        let subpasses = [subpass];
+        // The single depth/stencil image is shared by all frames in flight,
+        // so clearing it also waits for the previous frame's stencil tests.
+        let fragment_tests =
+            PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let dependencies = [SubpassDependency::default()
+            .src_subpass(SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | fragment_tests)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | fragment_tests)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )];
+
+        let render_pass_create_info = RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+
+        device
+            .create_render_pass(&render_pass_create_info, None)
+            .expect("Failed to create render pass")
+    }
+}
+
+/// One framebuffer per swapchain image, all sharing `depth_stencil_view`.
+fn create_framebuffers(
+    device: &Device,
+    render_pass: RenderPass,
+    image_views: &[ImageView],
+    depth_stencil_view: Option<ImageView>,
+    extent: Extent2D,
+) -> Vec<Framebuffer> {
+    unsafe {
+        image_views
+            .iter()
+            .map(|&image_view| {
+                let attachments: Vec<_> =
+                    [image_view].into_iter().chain(depth_stencil_view).collect();
+                let framebuffer_create_info = FramebufferCreateInfo::default()
+                    .render_pass(render_pass)
+                    .attachments(&attachments)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+                device
+                    .create_framebuffer(&framebuffer_create_info, None)
+                    .expect("Failed to create framebuffer")
+            })
+            .collect()
+    }
+}
+
+unsafe extern "system" fn debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    user_data: *mut std::os::raw::c_void,
+) -> u32 {
+    let callback_data = *p_callback_data;
+    let message_id_number = callback_data.message_id_number;
+    let message_id_name = if callback_data.p_message_id_name.is_null() {
+        Cow::from("")
+    } else {
+        ffi::CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy()
+    };
+    let debug_state = &*user_data.cast::<DebugState>();
+    if debug_state
+        .config
+        .ignores(&message_id_name, message_id_number)
+    {
+        return vk::FALSE;
+    }
+
+    let message = if callback_data.p_message.is_null() {
+        Cow::from("")
+    } else {
+        ffi::CStr::from_ptr(callback_data.p_message).to_string_lossy()
+    };
+    let formatted = format!(
+        "{message_severity:?}:{message_type:?}:{message_id_name} {message_id_number}:{message}"
+    );
+    println!("{formatted}\n");
+    if debug_state.config.capture_messages {
+        debug_state
+            .captured_messages
+            .lock()
+            .unwrap()
+            .push(formatted);
+    }
+    if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        && message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION)
+    {
+        debug_state
+            .validation_errors
+            .lock()
+            .unwrap()
+            .push(format!("{message_id_name} {message_id_number}: {message}"));
+    }
+    vk::FALSE
+}
+
+/// Raw handles are destroyed here once the device is idle. Wrapped resources
+/// (pipelines, descriptor allocators) release themselves when the fields drop
+/// afterwards, and each holds the `DeviceContext` alive until it has.
+impl Drop for BaseConfig {
+    fn drop(&mut self) {
+        unsafe {
+            // A lost device fails the wait, but its objects still have to be destroyed.
+            if let Err(err) = self.context.device.device_wait_idle() {
+                println!("Failed to wait for device idle: {err}");
+            }
+            self.deletion_queue.flush_all();
+
+            for &semaphore in self
+                .image_available_semaphores
+                .iter()
+                .chain(self.render_finished_semaphores.iter())
+            {
+                self.context.device.destroy_semaphore(semaphore, None);
+            }
+            for &fence in &self.in_flight_fences {
+                self.context.device.destroy_fence(fence, None);
+            }
+            if let Some(frame_timeline) = &self.frame_timeline {
+                self.context
+                    .device
+                    .destroy_semaphore(frame_timeline.semaphore, None);
+            }
+            if let Some(query_pool) = self.timestamp_query_pool {
+                self.context.device.destroy_query_pool(query_pool, None);
+            }
+            self.context
+                .device
+                .destroy_command_pool(self.command_pool, None);
+            for &framebuffer in &self.framebuffers {
+                self.context.device.destroy_framebuffer(framebuffer, None);
+            }
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            for set_layout in self
+                .empty_set_layout
+                .iter()
+                .chain([&self.uniform_set_layout])
+            {
+                self.context
+                    .device
+                    .destroy_descriptor_set_layout(*set_layout, None);
+            }
+            self.context
+                .device
+                .destroy_render_pass(self.render_pass, None);
+            for &image_view in &self.swapchain_image_views {
+                self.context.device.destroy_image_view(image_view, None);
+            }
+            self.swapchain_device
+                .destroy_swapchain(self.swapchain, None);
+
+            match self
+                .context
+                .device
+                .get_pipeline_cache_data(self.pipeline_cache)
+            {
+                Ok(data) => {
+                    if let Err(err) = fs::write(PIPELINE_CACHE_PATH, data) {
+                        println!("Failed to write pipeline cache to {PIPELINE_CACHE_PATH}: {err}");
+                    }
+                }
+                Err(err) => println!("Failed to retrieve pipeline cache data: {err}"),
+            }
+            self.context
+                .device
+                .destroy_pipeline_cache(self.pipeline_cache, None);
+            self.surface_instance.destroy_surface(self.surface, None);
+            if let Some(debug_utils_messenger) = self.debug_utils_messenger {
+                self.debug_instance
+                    .destroy_debug_utils_messenger(debug_utils_messenger, None);
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::{CullModeFlags, FrontFace};
+
+    use crate::engine::primitives;
+
+    #[test]
+    fn presents_from_the_graphics_family_when_it_can() {
+        let families = [
+            (QueueFlags::TRANSFER, true),
+            (QueueFlags::GRAPHICS | QueueFlags::COMPUTE, true),
+        ];
+        let indices = pick_queue_families(&families, QueueFlags::GRAPHICS).unwrap();
+        assert_eq!(
+            indices,
+            QueueFamilyIndices {
+                graphics_family: 1,
+                presentation_family: 1,
+            }
+        );
+        assert!(indices.is_shared());
+    }
+
+    #[test]
+    fn falls_back_to_a_separate_present_family() {
+        let families = [
+            (QueueFlags::GRAPHICS, false),
+            (QueueFlags::COMPUTE, false),
+            (QueueFlags::TRANSFER, true),
+        ];
+        let indices = pick_queue_families(&families, QueueFlags::GRAPHICS).unwrap();
+        assert_eq!(
+            indices,
+            QueueFamilyIndices {
+                graphics_family: 0,
+                presentation_family: 2,
+            }
+        );
+        assert!(!indices.is_shared());
+    }
+
+    #[test]
+    fn no_present_family_is_reported() {
+        let families = [(QueueFlags::GRAPHICS, false), (QueueFlags::COMPUTE, false)];
+        assert!(matches!(
+            pick_queue_families(&families, QueueFlags::GRAPHICS),
+            Err(EngineError::NoPresentQueue)
+        ));
+    }
+
+    #[test]
+    fn device_types_rank_discrete_first() {
+        let ranked = [
+            PhysicalDeviceType::DISCRETE_GPU,
+            PhysicalDeviceType::INTEGRATED_GPU,
+            PhysicalDeviceType::VIRTUAL_GPU,
+            PhysicalDeviceType::CPU,
+            PhysicalDeviceType::OTHER,
+        ];
+        for pair in ranked.windows(2) {
+            assert!(device_type_score(pair[0]) > device_type_score(pair[1]));
+        }
+    }
+
+    #[test]
+    fn mailbox_gets_three_images_within_the_surface_limits() {
+        let capabilities = |min_image_count, max_image_count| SurfaceCapabilitiesKHR {
+            min_image_count,
+            max_image_count,
+            ..Default::default()
+        };
+        let mailbox = PresentModeKHR::MAILBOX;
+        let fifo = PresentModeKHR::FIFO;
+        assert_eq!(desired_image_count(&capabilities(1, 0), mailbox), 3);
+        assert_eq!(desired_image_count(&capabilities(1, 0), fifo), 2);
+        // One more than a larger minimum, and no more than the maximum.
+        assert_eq!(desired_image_count(&capabilities(3, 8), mailbox), 4);
+        assert_eq!(desired_image_count(&capabilities(2, 3), mailbox), 3);
+        assert_eq!(desired_image_count(&capabilities(1, 2), mailbox), 2);
+    }
+
+    #[test]
+    fn no_graphics_family_is_reported() {
+        let families = [(QueueFlags::COMPUTE, true)];
+        assert!(matches!(
+            pick_queue_families(&families, QueueFlags::GRAPHICS),
+            Err(EngineError::NoSuitableDevice)
+        ));
+    }
+
+    /// Runs the quad from `primitives`, which winds counter-clockwise seen
+    /// from +Z, through the projection Y flip the primitives are meant for
+    /// and the default viewport, then applies Vulkan's front face rule with
+    /// the default material. Changing the default winding, viewport flip or
+    /// front face alone makes the quad a back face, which `cull_mode: BACK`
+    /// would discard.
+    #[test]
+    fn known_wound_quad_is_front_facing_with_the_defaults() {
+        let extent = Extent2D {
+            width: 640,
+            height: 480,
+        };
+        let viewport = frame_viewport(extent, RendererSettings::default().flip_viewport_y);
+        let desc = MaterialDesc::default();
+        assert_eq!(desc.cull_mode, CullModeFlags::BACK);
+
+        let (vertices, indices) = primitives::quad();
+        for triangle in indices.chunks(3) {
+            let framebuffer = [0, 1, 2].map(|corner| {
+                let [x, y, _] = vertices[triangle[corner] as usize].position;
+                // A Vulkan style projection puts +Y down in clip space.
+                let (clip_x, clip_y) = (x, -y);
+                (
+                    viewport.x + (clip_x + 1.0) * 0.5 * viewport.width,
+                    viewport.y + (clip_y + 1.0) * 0.5 * viewport.height,
+                )
+            });
+            // Signed area as the Vulkan spec defines it for polygon facing:
+            // positive is counter-clockwise in framebuffer coordinates.
+            let area = -0.5
+                * (0..3)
+                    .map(|i| {
+                        let (x0, y0) = framebuffer[i];
+                        let (x1, y1) = framebuffer[(i + 1) % 3];
+                        x0 * y1 - x1 * y0
+                    })
+                    .sum::<f32>();
+            let front_facing = if desc.front_face == FrontFace::CLOCKWISE {
+                area < 0.0
+            } else {
+                area > 0.0
+            };
+            assert!(front_facing, "Triangle {triangle:?} would be culled");
+        }
+    }
+
+    /// A draw identified by its `vertex_count`.
+    fn test_draw(id: u32, material: usize, camera_distance: f32) -> DrawCall {
+        DrawCall {
+            material: MaterialId::new(material),
+            vertex_count: id,
+            push_constants: Vec::new(),
+            camera_distance,
+            predicate: None,
+            depth_bias: None,
+            line_width: None,
+            uniforms: None,
+        }
+    }
+
+    /// Materials 2 and up blend.
+    fn sorted_ids(mut draws: Vec<DrawCall>) -> Vec<u32> {
+        sort_draws(&mut draws, |material| material.index() >= 2);
+        draws.iter().map(|draw| draw.vertex_count).collect()
+    }
+
+    #[test]
+    fn opaque_draws_are_grouped_by_material_before_transparent_ones() {
+        let draws = vec![
+            test_draw(0, 1, 0.0),
+            test_draw(1, 2, 1.0),
+            test_draw(2, 0, 0.0),
+            test_draw(3, 1, 0.0),
+            test_draw(4, 0, 0.0),
+        ];
+        assert_eq!(sorted_ids(draws), [2, 4, 0, 3, 1]);
+    }
+
+    #[test]
+    fn transparent_draws_go_back_to_front_with_the_overlay_last() {
+        // Material 3 stands in for the overlay, drawn at distance 0 through
+        // `draw_with_push_constants`.
+        let draws = vec![
+            test_draw(0, 3, 0.0),
+            test_draw(1, 2, 1.0),
+            test_draw(2, 2, 5.0),
+            test_draw(3, 0, 0.0),
+            test_draw(4, 2, 3.0),
+        ];
+        assert_eq!(sorted_ids(draws), [3, 2, 4, 1, 0]);
+    }
+
+    #[test]
+    fn overlapping_transparent_draws_sort_the_same_in_any_order() {
+        let near = test_draw(0, 2, 1.0);
+        let far = test_draw(1, 2, 2.0);
+        assert_eq!(sorted_ids(vec![near.clone(), far.clone()]), [1, 0]);
+        assert_eq!(sorted_ids(vec![far, near]), [1, 0]);
+    }
+
+    #[test]
+    fn transparent_draws_at_the_same_distance_keep_submission_order() {
+        let draws = vec![
+            test_draw(0, 3, 0.0),
+            test_draw(1, 2, 0.0),
+            test_draw(2, 3, 0.0),
+        ];
+        assert_eq!(sorted_ids(draws), [0, 1, 2]);
     }
 }
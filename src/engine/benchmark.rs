@@ -0,0 +1,106 @@
+/// Collects the frame times of a fixed number of frames and summarizes them
+/// as a single line of JSON, for tracking performance across runs.
+pub struct Benchmark {
+    frames: usize,
+    cpu_frame_ms: Vec<f32>,
+    gpu_frame_ms: Vec<f32>,
+    record_ms: Vec<f32>,
+}
+
+impl Benchmark {
+    pub fn new(frames: usize) -> Self {
+        Self {
+            frames,
+            cpu_frame_ms: Vec::with_capacity(frames),
+            gpu_frame_ms: Vec::with_capacity(frames),
+            record_ms: Vec::with_capacity(frames),
+        }
+    }
+
+    /// Adds one frame. `gpu_frame_ms` is `None` without timestamp support and
+    /// for the first frames, before any has completed. `record_ms` is the
+    /// time spent recording the frame's command buffers, see
+    /// `BaseConfig::last_record_ms`.
+    pub fn record(&mut self, cpu_frame_ms: f32, gpu_frame_ms: Option<f32>, record_ms: Option<f32>) {
+        self.cpu_frame_ms.push(cpu_frame_ms);
+        self.gpu_frame_ms.extend(gpu_frame_ms);
+        self.record_ms.extend(record_ms);
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.cpu_frame_ms.len() >= self.frames
+    }
+
+    /// E.g. `{"frames":1000,"cpu_ms":{"min":0.21,...},"gpu_ms":null,
+    /// "record_ms":{...}}`, with `min`, `max`, `mean` and `p99` for each of
+    /// CPU, GPU and command buffer recording.
+    pub fn report(&self) -> String {
+        format!(
+            "{{\"frames\":{},\"cpu_ms\":{},\"gpu_ms\":{},\"record_ms\":{}}}",
+            self.cpu_frame_ms.len(),
+            summarize(&self.cpu_frame_ms),
+            summarize(&self.gpu_frame_ms),
+            summarize(&self.record_ms)
+        )
+    }
+}
+
+fn summarize(samples: &[f32]) -> String {
+    if samples.is_empty() {
+        return "null".to_owned();
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let mean = sorted.iter().sum::<f32>() / sorted.len() as f32;
+    // Nearest rank: the smallest sample at or above 99% of all of them.
+    let p99 = sorted[(sorted.len() * 99).div_ceil(100) - 1];
+    format!(
+        "{{\"min\":{:.3},\"max\":{:.3},\"mean\":{mean:.3},\"p99\":{p99:.3}}}",
+        sorted[0],
+        sorted[sorted.len() - 1]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_known_samples() {
+        // Shuffled, so the summary has to sort them.
+        let samples: Vec<f32> = (1..=100).map(|i| ((i * 37) % 100 + 1) as f32).collect();
+        assert_eq!(
+            summarize(&samples),
+            r#"{"min":1.000,"max":100.000,"mean":50.500,"p99":99.000}"#
+        );
+    }
+
+    #[test]
+    fn a_single_sample_is_every_statistic() {
+        assert_eq!(
+            summarize(&[2.5]),
+            r#"{"min":2.500,"max":2.500,"mean":2.500,"p99":2.500}"#
+        );
+    }
+
+    #[test]
+    fn no_samples_summarize_as_null() {
+        assert_eq!(summarize(&[]), "null");
+    }
+
+    #[test]
+    fn report_without_gpu_times() {
+        let mut benchmark = Benchmark::new(2);
+        benchmark.record(1.0, None, Some(0.5));
+        assert!(!benchmark.is_done());
+        benchmark.record(3.0, None, Some(0.5));
+        assert!(benchmark.is_done());
+        assert_eq!(
+            benchmark.report(),
+            concat!(
+                r#"{"frames":2,"cpu_ms":{"min":1.000,"max":3.000,"mean":2.000,"p99":3.000},"#,
+                r#""gpu_ms":null,"record_ms":{"min":0.500,"max":0.500,"mean":0.500,"p99":0.500}}"#
+            )
+        );
+    }
+}
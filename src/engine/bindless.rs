@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use ash::vk::{
+    DescriptorBindingFlags, DescriptorImageInfo, DescriptorPool, DescriptorPoolCreateFlags,
+    DescriptorPoolCreateInfo, DescriptorPoolSize, DescriptorSet, DescriptorSetAllocateInfo,
+    DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutBindingFlagsCreateInfo,
+    DescriptorSetLayoutCreateFlags, DescriptorSetLayoutCreateInfo,
+    DescriptorSetVariableDescriptorCountAllocateInfo, DescriptorType, ImageLayout, ImageView,
+    PhysicalDeviceDescriptorIndexingProperties, PhysicalDeviceProperties2, Sampler,
+    ShaderStageFlags, WriteDescriptorSet,
+};
+
+use super::device_context::DeviceContext;
+
+/// Upper bound on the table size, lowered further to what the device allows.
+const MAX_TEXTURES: u32 = 4096;
+
+/// One descriptor set holding an array of combined image samplers, bound to
+/// set 0 for every draw, which shaders index by a per-draw value:
+/// `layout(set = 0, binding = 0) uniform sampler2D textures[];`, indexed
+/// through `nonuniformEXT` when the index varies within a draw. Slots are
+/// written with update-after-bind, so textures can be added while frames
+/// using the set are in flight.
+pub(super) struct BindlessTextures {
+    context: Arc<DeviceContext>,
+    set_layout: DescriptorSetLayout,
+    pool: DescriptorPool,
+    set: DescriptorSet,
+    capacity: u32,
+    len: u32,
+}
+
+impl BindlessTextures {
+    /// The device must have been created with the descriptor indexing
+    /// features this relies on.
+    pub fn new(context: &Arc<DeviceContext>) -> Self {
+        let device = &context.device;
+        let capacity = MAX_TEXTURES.min(max_update_after_bind_textures(context));
+        unsafe {
+            let bindings = [DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(capacity)
+                .stage_flags(ShaderStageFlags::FRAGMENT)];
+            let binding_flags = [DescriptorBindingFlags::PARTIALLY_BOUND
+                | DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+                | DescriptorBindingFlags::UPDATE_AFTER_BIND];
+            let mut binding_flags_info =
+                DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+            let set_layout = device
+                .create_descriptor_set_layout(
+                    &DescriptorSetLayoutCreateInfo::default()
+                        .flags(DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+                        .bindings(&bindings)
+                        .push_next(&mut binding_flags_info),
+                    None,
+                )
+                .expect("Failed to create bindless descriptor set layout");
+
+            let pool_sizes = [DescriptorPoolSize {
+                ty: DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: capacity,
+            }];
+            let pool = device
+                .create_descriptor_pool(
+                    &DescriptorPoolCreateInfo::default()
+                        .flags(DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+                        .max_sets(1)
+                        .pool_sizes(&pool_sizes),
+                    None,
+                )
+                .expect("Failed to create bindless descriptor pool");
+
+            let set_layouts = [set_layout];
+            let descriptor_counts = [capacity];
+            let mut variable_count_info =
+                DescriptorSetVariableDescriptorCountAllocateInfo::default()
+                    .descriptor_counts(&descriptor_counts);
+            let set = device
+                .allocate_descriptor_sets(
+                    &DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(pool)
+                        .set_layouts(&set_layouts)
+                        .push_next(&mut variable_count_info),
+                )
+                .expect("Failed to allocate bindless descriptor set")[0];
+
+            Self {
+                context: Arc::clone(context),
+                set_layout,
+                pool,
+                set,
+                capacity,
+                len: 0,
+            }
+        }
+    }
+
+    pub fn set_layout(&self) -> DescriptorSetLayout {
+        self.set_layout
+    }
+
+    pub fn set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    /// Writes the next free slot and returns its index, or `None` once all
+    /// `capacity` slots are taken. The image has to be in
+    /// `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn register(&mut self, image_view: ImageView, sampler: Sampler) -> Option<u32> {
+        if self.len == self.capacity {
+            return None;
+        }
+        let index = self.len;
+        let image_info = [DescriptorImageInfo::default()
+            .sampler(sampler)
+            .image_view(image_view)
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &[WriteDescriptorSet::default()
+                    .dst_set(self.set)
+                    .dst_binding(0)
+                    .dst_array_element(index)
+                    .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&image_info)],
+                &[],
+            );
+        }
+        self.len += 1;
+        Some(index)
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+}
+
+impl Drop for BindlessTextures {
+    fn drop(&mut self) {
+        unsafe {
+            // Frees the set along with the pool.
+            self.context.device.destroy_descriptor_pool(self.pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+/// How many update-after-bind combined image samplers a fragment shader can
+/// reach, limited by both the sampler and the sampled image counts.
+fn max_update_after_bind_textures(context: &DeviceContext) -> u32 {
+    let mut indexing_properties = PhysicalDeviceDescriptorIndexingProperties::default();
+    unsafe {
+        context.instance.get_physical_device_properties2(
+            context.physical_device,
+            &mut PhysicalDeviceProperties2::default().push_next(&mut indexing_properties),
+        );
+    }
+    indexing_properties
+        .max_per_stage_descriptor_update_after_bind_samplers
+        .min(indexing_properties.max_per_stage_descriptor_update_after_bind_sampled_images)
+        .min(indexing_properties.max_descriptor_set_update_after_bind_samplers)
+        .min(indexing_properties.max_descriptor_set_update_after_bind_sampled_images)
+}
@@ -0,0 +1,321 @@
+use std::sync::Arc;
+
+use ash::vk::{
+    self, BufferCreateInfo, BufferUsageFlags, DeviceMemory, DeviceSize, MemoryAllocateInfo,
+    MemoryPropertyFlags, SharingMode,
+};
+
+use super::{device_context::DeviceContext, memory::find_memory_type_index};
+
+/// Size of each memory block, unless a single buffer needs more.
+const DEFAULT_BLOCK_SIZE: DeviceSize = 64 * 1024 * 1024;
+
+/// A buffer placed inside one of the blocks of a `BufferPool`. It has to be
+/// handed back to `BufferPool::free` once the GPU is done with it. Dropping
+/// it does not release it: that needs mutable access to the pool, which the
+/// buffer cannot hold while others are allocated, and only the caller knows
+/// when the GPU has stopped using it. A buffer that is never freed keeps its
+/// range until the pool is dropped, which reports it.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buffer: vk::Buffer,
+    block: usize,
+    offset: DeviceSize,
+    /// Bytes reserved in the block, which can exceed the requested size.
+    reserved: DeviceSize,
+    size: DeviceSize,
+}
+
+impl PooledBuffer {
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn size(&self) -> DeviceSize {
+        self.size
+    }
+}
+
+struct Block {
+    memory: DeviceMemory,
+    memory_type_index: u32,
+    size: DeviceSize,
+    /// Free `(offset, size)` ranges, sorted by offset and never adjacent.
+    free_ranges: Vec<(DeviceSize, DeviceSize)>,
+}
+
+impl Block {
+    /// Reserves `size` bytes at an offset aligned to `alignment`, first fit.
+    fn reserve(&mut self, size: DeviceSize, alignment: DeviceSize) -> Option<DeviceSize> {
+        let (index, offset) =
+            self.free_ranges
+                .iter()
+                .enumerate()
+                .find_map(|(index, &(start, length))| {
+                    let offset = start.next_multiple_of(alignment);
+                    (offset + size <= start + length).then_some((index, offset))
+                })?;
+
+        // The alignment padding and the tail stay free.
+        let (start, length) = self.free_ranges.remove(index);
+        let mut insert_at = index;
+        if offset > start {
+            self.free_ranges.insert(insert_at, (start, offset - start));
+            insert_at += 1;
+        }
+        if offset + size < start + length {
+            self.free_ranges
+                .insert(insert_at, (offset + size, start + length - offset - size));
+        }
+        Some(offset)
+    }
+
+    /// Returns a range to the free list, merging it with its neighbours.
+    fn release(&mut self, offset: DeviceSize, size: DeviceSize) {
+        let index = self
+            .free_ranges
+            .partition_point(|&(start, _)| start < offset);
+        self.free_ranges.insert(index, (offset, size));
+        if index + 1 < self.free_ranges.len() {
+            let (start, length) = self.free_ranges[index];
+            let (next_start, next_length) = self.free_ranges[index + 1];
+            if start + length == next_start {
+                self.free_ranges[index].1 += next_length;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+        if index > 0 {
+            let (previous_start, previous_length) = self.free_ranges[index - 1];
+            let (start, length) = self.free_ranges[index];
+            if previous_start + previous_length == start {
+                self.free_ranges[index - 1].1 += length;
+                self.free_ranges.remove(index);
+            }
+        }
+    }
+}
+
+/// Places many buffers in a few large memory allocations instead of one
+/// allocation each, which would run into `maxMemoryAllocationCount` (often
+/// 4096) and is slow. Freed ranges are reused by later buffers. Blocks are
+/// only released when the pool is dropped.
+pub struct BufferPool {
+    context: Arc<DeviceContext>,
+    memory_flags: MemoryPropertyFlags,
+    blocks: Vec<Block>,
+}
+
+impl BufferPool {
+    pub fn new(context: &Arc<DeviceContext>, memory_flags: MemoryPropertyFlags) -> Self {
+        Self {
+            context: Arc::clone(context),
+            memory_flags,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Creates a buffer and binds it to free space in an existing block, or
+    /// in a new block when none has room.
+    pub fn allocate(&mut self, size: DeviceSize, usage: BufferUsageFlags) -> PooledBuffer {
+        let device = &self.context.device;
+        unsafe {
+            let buffer = device
+                .create_buffer(
+                    &BufferCreateInfo::default()
+                        .size(size)
+                        .usage(usage)
+                        .sharing_mode(SharingMode::EXCLUSIVE),
+                    None,
+                )
+                .expect("Failed to create pooled buffer");
+            let requirements = device.get_buffer_memory_requirements(buffer);
+
+            let existing = self
+                .blocks
+                .iter_mut()
+                .enumerate()
+                .find_map(|(index, block)| {
+                    if requirements.memory_type_bits & (1 << block.memory_type_index) == 0 {
+                        return None;
+                    }
+                    block
+                        .reserve(requirements.size, requirements.alignment)
+                        .map(|offset| (index, offset))
+                });
+            let (block, offset) = match existing {
+                Some(placement) => placement,
+                None => {
+                    let block = self.allocate_block(
+                        requirements.size.max(DEFAULT_BLOCK_SIZE),
+                        requirements.memory_type_bits,
+                    );
+                    self.blocks.push(block);
+                    let offset = self
+                        .blocks
+                        .last_mut()
+                        .unwrap()
+                        .reserve(requirements.size, requirements.alignment)
+                        .expect("New memory block too small for its first buffer");
+                    (self.blocks.len() - 1, offset)
+                }
+            };
+
+            device
+                .bind_buffer_memory(buffer, self.blocks[block].memory, offset)
+                .expect("Failed to bind pooled buffer memory");
+
+            PooledBuffer {
+                buffer,
+                block,
+                offset,
+                reserved: requirements.size,
+                size,
+            }
+        }
+    }
+
+    /// Destroys `buffer` and makes its range available again. The GPU must
+    /// no longer be using it.
+    pub fn free(&mut self, buffer: PooledBuffer) {
+        unsafe {
+            self.context.device.destroy_buffer(buffer.buffer, None);
+        }
+        self.blocks[buffer.block].release(buffer.offset, buffer.reserved);
+    }
+
+    /// Number of device memory allocations backing the pool.
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn allocate_block(&self, size: DeviceSize, memory_type_bits: u32) -> Block {
+        unsafe {
+            let memory_properties = self
+                .context
+                .instance
+                .get_physical_device_memory_properties(self.context.physical_device);
+            let memory_type_index =
+                find_memory_type_index(&memory_properties, memory_type_bits, self.memory_flags)
+                    .expect("No suitable memory type");
+            let memory = self
+                .context
+                .device
+                .allocate_memory(
+                    &MemoryAllocateInfo::default()
+                        .allocation_size(size)
+                        .memory_type_index(memory_type_index),
+                    None,
+                )
+                .expect("Failed to allocate buffer pool block");
+            Block {
+                memory,
+                memory_type_index,
+                size,
+                free_ranges: vec![(0, size)],
+            }
+        }
+    }
+}
+
+/// Every buffer must have been freed, and the GPU be done with them.
+impl Drop for BufferPool {
+    fn drop(&mut self) {
+        for block in &self.blocks {
+            if block.free_ranges != [(0, block.size)] {
+                println!("Buffer pool dropped with buffers still allocated");
+            }
+            unsafe {
+                self.context.device.free_memory(block.memory, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: DeviceSize) -> Block {
+        Block {
+            memory: DeviceMemory::null(),
+            memory_type_index: 0,
+            size,
+            free_ranges: vec![(0, size)],
+        }
+    }
+
+    #[test]
+    fn reserve_aligns_and_keeps_the_padding_free() {
+        let mut block = block(1024);
+        assert_eq!(block.reserve(10, 1), Some(0));
+        assert_eq!(block.reserve(100, 256), Some(256));
+        assert_eq!(block.free_ranges, [(10, 246), (356, 668)]);
+        // The padding is used by a later, smaller reservation.
+        assert_eq!(block.reserve(16, 16), Some(16));
+        assert_eq!(block.free_ranges, [(10, 6), (32, 224), (356, 668)]);
+    }
+
+    #[test]
+    fn reserve_fails_without_room() {
+        let mut block = block(256);
+        assert_eq!(block.reserve(200, 1), Some(0));
+        assert_eq!(block.reserve(100, 1), None);
+        // Enough bytes are free, but not at an aligned offset.
+        assert_eq!(block.reserve(40, 128), None);
+    }
+
+    #[test]
+    fn release_merges_with_both_neighbours() {
+        let mut block = block(300);
+        let offsets: Vec<_> = (0..3).map(|_| block.reserve(100, 1).unwrap()).collect();
+        assert_eq!(offsets, [0, 100, 200]);
+        assert!(block.free_ranges.is_empty());
+
+        block.release(0, 100);
+        block.release(200, 100);
+        assert_eq!(block.free_ranges, [(0, 100), (200, 100)]);
+        block.release(100, 100);
+        assert_eq!(block.free_ranges, [(0, 300)]);
+    }
+
+    #[test]
+    fn five_thousand_buffers_reuse_the_block() {
+        const COUNT: usize = 5000;
+        let size = 64 * 1024 * 1024;
+        let mut block = block(size);
+        // A fixed linear congruential sequence, for varied sizes and a
+        // shuffled release order without a dependency on `rand`.
+        let mut state = 0x2545_f491_u64;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            state >> 33
+        };
+
+        for _ in 0..2 {
+            let mut reserved: Vec<_> = (0..COUNT)
+                .map(|_| {
+                    let length = 1 + next() % 4096;
+                    let alignment = 1 << (next() % 9);
+                    let offset = block.reserve(length, alignment).unwrap();
+                    assert_eq!(offset % alignment, 0);
+                    (offset, length)
+                })
+                .collect();
+
+            let mut sorted = reserved.clone();
+            sorted.sort_unstable();
+            for pair in sorted.windows(2) {
+                assert!(pair[0].0 + pair[0].1 <= pair[1].0, "{pair:?} overlap");
+            }
+            assert!(sorted.last().map_or(0, |&(offset, length)| offset + length) <= size);
+
+            for index in (1..reserved.len()).rev() {
+                reserved.swap(index, next() as usize % (index + 1));
+            }
+            for (offset, length) in reserved {
+                block.release(offset, length);
+            }
+            assert_eq!(block.free_ranges, [(0, size)]);
+        }
+    }
+}
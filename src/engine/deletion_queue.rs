@@ -0,0 +1,36 @@
+use std::collections::VecDeque;
+
+/// Destroy closures for resources that frames still in flight may use. Each
+/// closure is tagged with the number of frames submitted when it was queued
+/// and runs once that many frames have completed on the GPU.
+#[derive(Default)]
+pub struct DeletionQueue {
+    pending: VecDeque<(u64, Box<dyn FnOnce()>)>,
+}
+
+impl DeletionQueue {
+    pub fn push(&mut self, frames_submitted: u64, destroy: impl FnOnce() + 'static) {
+        self.pending
+            .push_back((frames_submitted, Box::new(destroy)));
+    }
+
+    /// Runs every closure whose frames are covered by `frames_completed`, in
+    /// the order they were queued.
+    pub fn flush(&mut self, frames_completed: u64) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|&(frames_submitted, _)| frames_submitted <= frames_completed)
+        {
+            let (_, destroy) = self.pending.pop_front().unwrap();
+            destroy();
+        }
+    }
+
+    /// Runs every pending closure. The device must be idle.
+    pub fn flush_all(&mut self) {
+        for (_, destroy) in self.pending.drain(..) {
+            destroy();
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use ash::vk::{
+    self, DescriptorPool, DescriptorPoolCreateInfo, DescriptorPoolResetFlags, DescriptorPoolSize,
+    DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorType,
+};
+
+use super::device_context::DeviceContext;
+
+const MAX_SETS_PER_POOL: u32 = 4092;
+
+/// How many descriptors of `descriptor_type` a pool reserves per set.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolSizeRatio {
+    pub descriptor_type: DescriptorType,
+    pub ratio: f32,
+}
+
+/// Hands out descriptor sets from a list of pools, creating a larger pool
+/// whenever the current one is exhausted instead of failing the allocation.
+/// Sets are freed all at once through `reset`.
+pub struct DescriptorAllocator {
+    context: Arc<DeviceContext>,
+    pool_size_ratios: Vec<PoolSizeRatio>,
+    sets_per_pool: u32,
+    ready_pools: Vec<DescriptorPool>,
+    full_pools: Vec<DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(
+        context: &Arc<DeviceContext>,
+        initial_sets: u32,
+        pool_size_ratios: &[PoolSizeRatio],
+    ) -> Self {
+        let mut allocator = Self {
+            context: Arc::clone(context),
+            pool_size_ratios: pool_size_ratios.to_vec(),
+            sets_per_pool: initial_sets,
+            ready_pools: Vec::new(),
+            full_pools: Vec::new(),
+        };
+        let pool = allocator.create_pool(initial_sets);
+        allocator.ready_pools.push(pool);
+        allocator
+    }
+
+    pub fn allocate(&mut self, layout: DescriptorSetLayout) -> DescriptorSet {
+        let layouts = [layout];
+        loop {
+            let reused_pool = !self.ready_pools.is_empty();
+            let pool = self.get_pool();
+            let allocate_info = DescriptorSetAllocateInfo::default()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts);
+
+            match unsafe { self.context.device.allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => {
+                    self.ready_pools.push(pool);
+                    return sets[0];
+                }
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL)
+                    if reused_pool =>
+                {
+                    self.full_pools.push(pool);
+                }
+                Err(err) => panic!("Failed to allocate descriptor set: {err}"),
+            }
+        }
+    }
+
+    /// Returns every set handed out so far to its pool. Sets allocated before
+    /// the reset must no longer be in use by the GPU.
+    pub fn reset(&mut self) {
+        unsafe {
+            for &pool in self.ready_pools.iter().chain(self.full_pools.iter()) {
+                self.context
+                    .device
+                    .reset_descriptor_pool(pool, DescriptorPoolResetFlags::empty())
+                    .expect("Failed to reset descriptor pool");
+            }
+        }
+        self.ready_pools.append(&mut self.full_pools);
+    }
+
+    fn get_pool(&mut self) -> DescriptorPool {
+        if let Some(pool) = self.ready_pools.pop() {
+            return pool;
+        }
+
+        // Grow geometrically so a burst of allocations settles on a few pools.
+        self.sets_per_pool =
+            (self.sets_per_pool + self.sets_per_pool.div_ceil(2)).min(MAX_SETS_PER_POOL);
+        self.create_pool(self.sets_per_pool)
+    }
+
+    fn create_pool(&self, max_sets: u32) -> DescriptorPool {
+        let pool_sizes: Vec<DescriptorPoolSize> = self
+            .pool_size_ratios
+            .iter()
+            .map(|pool_size_ratio| DescriptorPoolSize {
+                ty: pool_size_ratio.descriptor_type,
+                descriptor_count: ((pool_size_ratio.ratio * max_sets as f32) as u32).max(1),
+            })
+            .collect();
+
+        unsafe {
+            self.context
+                .device
+                .create_descriptor_pool(
+                    &DescriptorPoolCreateInfo::default()
+                        .max_sets(max_sets)
+                        .pool_sizes(&pool_sizes),
+                    None,
+                )
+                .expect("Failed to create descriptor pool")
+        }
+    }
+}
+
+impl Drop for DescriptorAllocator {
+    fn drop(&mut self) {
+        unsafe {
+            for pool in self.ready_pools.drain(..).chain(self.full_pools.drain(..)) {
+                self.context.device.destroy_descriptor_pool(pool, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use ash::{vk::PhysicalDevice, Device, Entry, Instance};
+
+use super::base_configuration::DebugState;
+
+/// The loader, instance and logical device shared by the engine and every
+/// resource created from it. Resources hold an `Arc<DeviceContext>` so the
+/// device outlives them; the device and instance are destroyed when the
+/// last reference goes away.
+pub struct DeviceContext {
+    pub entry: Entry,
+    pub instance: Instance,
+    pub physical_device: PhysicalDevice,
+    pub device: Device,
+    /// Target of the debug messenger's user data pointer. The messenger
+    /// chained into instance creation also reports during
+    /// `destroy_instance`, so this has to be dropped after it, and boxed so
+    /// the pointer stays valid.
+    pub(super) debug_state: Box<DebugState>,
+}
+
+impl Drop for DeviceContext {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::{error::Error, fmt};
+
+use ash::{vk, LoadingError};
+
+/// Failures the application is expected to handle, rather than bugs that
+/// panic where they are detected.
+#[derive(Debug)]
+pub enum EngineError {
+    /// The Vulkan loader library could not be loaded, usually because no
+    /// Vulkan runtime is installed.
+    NoVulkanLoader(LoadingError),
+    /// No physical device offers the required queues and features.
+    NoSuitableDevice,
+    /// A device has a graphics queue, but none of its queue families can
+    /// present to the window surface.
+    NoPresentQueue,
+    /// Suitable devices were found, but creating a logical device failed on
+    /// every one of them. Holds the last failure.
+    DeviceCreationFailed(vk::Result),
+    /// The GPU hung or crashed, or the driver was reset. The device cannot be
+    /// used for anything but teardown afterwards.
+    DeviceLost,
+    /// Validation reported errors while `Application::with_strict_validation`
+    /// was on. Holds the messages.
+    ValidationErrors(Vec<String>),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::NoVulkanLoader(err) => write!(
+                f,
+                "Could not load the Vulkan loader ({err}); install a GPU driver with Vulkan \
+                 support, or the Vulkan runtime from https://vulkan.lunarg.com"
+            ),
+            EngineError::NoSuitableDevice => write!(f, "No suitable physical device found"),
+            EngineError::NoPresentQueue => write!(
+                f,
+                "No queue family can present to the window surface; check the display \
+                 server and GPU driver setup"
+            ),
+            EngineError::DeviceCreationFailed(err) => write!(
+                f,
+                "Creating a logical device failed on every suitable physical device, last \
+                 with {err}"
+            ),
+            EngineError::DeviceLost => write!(f, "The Vulkan device was lost"),
+            EngineError::ValidationErrors(errors) => {
+                write!(f, "{} validation error(s) reported", errors.len())
+            }
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EngineError::NoVulkanLoader(err) => Some(err),
+            EngineError::DeviceCreationFailed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    hint, thread,
+    time::{Duration, Instant},
+};
+
+/// The last stretch of a wait is spun rather than slept, since sleeps often
+/// overshoot by a millisecond or more.
+const SPIN_DURATION: Duration = Duration::from_millis(1);
+
+/// Caps the frame rate independently of the present mode, so uncapped modes
+/// like `IMMEDIATE` and `MAILBOX` don't run the GPU flat out.
+pub struct FrameTimer {
+    min_frame_time: Option<Duration>,
+    next_frame: Instant,
+}
+
+impl FrameTimer {
+    pub fn new(max_fps: Option<u32>) -> Self {
+        let mut frame_timer = Self {
+            min_frame_time: None,
+            next_frame: Instant::now(),
+        };
+        frame_timer.set_max_fps(max_fps);
+        frame_timer
+    }
+
+    /// `None` or `Some(0)` removes the cap.
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        self.min_frame_time = max_fps
+            .filter(|&max_fps| max_fps > 0)
+            .map(|max_fps| Duration::from_secs(1) / max_fps);
+        self.next_frame = Instant::now();
+    }
+
+    /// Blocks until the next frame may start.
+    pub fn wait(&mut self) {
+        let Some(min_frame_time) = self.min_frame_time else {
+            return;
+        };
+
+        let now = Instant::now();
+        if now >= self.next_frame {
+            // Running behind; start a new schedule instead of rushing frames
+            // to catch up.
+            self.next_frame = now + min_frame_time;
+            return;
+        }
+
+        let remaining = self.next_frame - now;
+        if remaining > SPIN_DURATION {
+            thread::sleep(remaining - SPIN_DURATION);
+        }
+        while Instant::now() < self.next_frame {
+            hint::spin_loop();
+        }
+        self.next_frame += min_frame_time;
+    }
+}
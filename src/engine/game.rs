@@ -0,0 +1,503 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+use ash::vk::{BufferUsageFlags, DeviceSize, Extent2D, ImageView, Sampler};
+use winit::keyboard::KeyCode;
+
+use super::{
+    base_configuration::BaseConfig,
+    error::EngineError,
+    material::{DepthBias, MaterialDesc, MaterialId},
+    resource::{DynamicUniformBuffer, MappedBuffer, PerFrameBuffer},
+};
+
+/// Per-frame hooks for the code running on top of the engine. `Application`
+/// calls `update` and then `render` on every redraw.
+pub trait Game {
+    /// Called once the renderer is up, before the first frame. This is the
+    /// place to register materials. Called again when the application
+    /// resumes after a suspension, since materials do not survive it.
+    fn init(&mut self, _ctx: &mut RenderContext) {}
+
+    fn update(&mut self, dt: f32, input: &InputState);
+
+    fn render(&mut self, ctx: &mut RenderContext);
+
+    /// Called when the window is resized, before the next `render`. Cameras
+    /// should update their aspect ratio here, and render targets sized after
+    /// the window be recreated. A minimized window reports zero size.
+    fn on_resize(&mut self, _width: u32, _height: u32) {}
+
+    /// Whether another frame should follow right away. Only consulted under
+    /// `ControlFlow::Wait`, where frames are otherwise drawn in response to
+    /// window events only.
+    fn wants_redraw(&self) -> bool {
+        true
+    }
+}
+
+/// Keys currently held down, by physical location.
+#[derive(Debug, Default)]
+pub struct InputState {
+    pressed_keys: HashSet<KeyCode>,
+}
+
+impl InputState {
+    pub fn is_pressed(&self, key_code: KeyCode) -> bool {
+        self.pressed_keys.contains(&key_code)
+    }
+
+    /// Whether any key bound to `action` in `input_map` is held down.
+    pub fn is_action_pressed<A: Copy + Eq + Hash>(
+        &self,
+        input_map: &InputMap<A>,
+        action: A,
+    ) -> bool {
+        input_map
+            .keys(action)
+            .iter()
+            .any(|&key_code| self.is_pressed(key_code))
+    }
+
+    pub(super) fn set_pressed(&mut self, key_code: KeyCode, pressed: bool) {
+        if pressed {
+            self.pressed_keys.insert(key_code);
+        } else {
+            self.pressed_keys.remove(&key_code);
+        }
+    }
+}
+
+/// Binds the game's own actions, usually an enum, to keys, so game logic
+/// does not depend on the physical layout. An action can have several keys
+/// and a key can trigger several actions. Bindings come from code or a config
+/// file, see `from_config`, and may change at any time, e.g. from a settings
+/// menu.
+#[derive(Clone, Debug)]
+pub struct InputMap<A> {
+    bindings: HashMap<A, Vec<KeyCode>>,
+}
+
+impl<A> Default for InputMap<A> {
+    fn default() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+}
+
+impl<A: Copy + Eq + Hash> InputMap<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `key_code` to the keys triggering `action`.
+    pub fn bind(&mut self, action: A, key_code: KeyCode) -> &mut Self {
+        let keys = self.bindings.entry(action).or_default();
+        if !keys.contains(&key_code) {
+            keys.push(key_code);
+        }
+        self
+    }
+
+    /// Makes `key_code` the only key triggering `action`.
+    pub fn rebind(&mut self, action: A, key_code: KeyCode) -> &mut Self {
+        self.bindings.insert(action, vec![key_code]);
+        self
+    }
+
+    /// Removes every key from `action`.
+    pub fn unbind(&mut self, action: A) {
+        self.bindings.remove(&action);
+    }
+
+    pub fn keys(&self, action: A) -> &[KeyCode] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl<A: Copy + Eq + Hash + FromStr> InputMap<A> {
+    /// Parses bindings from lines of `action = KeyCode`, with actions as
+    /// `A::from_str` reads them and keys named like the `KeyCode` variants,
+    /// e.g. `Jump = Space`. Binding an action again adds another key. Blank
+    /// lines and lines starting with `#` are skipped. Fails with
+    /// `ErrorKind::InvalidData` naming the line of the first malformed
+    /// entry, unknown action or unknown key.
+    pub fn from_config(config: &str) -> Result<Self, Error> {
+        let mut input_map = Self::new();
+        for (index, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Line {}: {message}", index + 1),
+                )
+            };
+            let (action, key) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected `action = KeyCode`, got {line:?}")))?;
+            let (action, key) = (action.trim(), key.trim());
+            let action = action
+                .parse()
+                .map_err(|_| invalid(format!("unknown action {action:?}")))?;
+            let key_code =
+                key_code_from_name(key).ok_or_else(|| invalid(format!("unknown key {key:?}")))?;
+            input_map.bind(action, key_code);
+        }
+        Ok(input_map)
+    }
+}
+
+/// The `KeyCode` whose variant is called `name`, for the keys games usually
+/// bind.
+fn key_code_from_name(name: &str) -> Option<KeyCode> {
+    macro_rules! key_codes {
+        ($($key_code:ident)*) => {
+            match name {
+                $(stringify!($key_code) => Some(KeyCode::$key_code),)*
+                _ => None,
+            }
+        };
+    }
+    key_codes!(
+        KeyA KeyB KeyC KeyD KeyE KeyF KeyG KeyH KeyI KeyJ KeyK KeyL KeyM
+        KeyN KeyO KeyP KeyQ KeyR KeyS KeyT KeyU KeyV KeyW KeyX KeyY KeyZ
+        Digit0 Digit1 Digit2 Digit3 Digit4 Digit5 Digit6 Digit7 Digit8 Digit9
+        F1 F2 F3 F4 F5 F6 F7 F8 F9 F10 F11 F12
+        ArrowUp ArrowDown ArrowLeft ArrowRight
+        Space Enter Escape Tab Backspace Delete Insert Home End PageUp PageDown
+        ShiftLeft ShiftRight ControlLeft ControlRight AltLeft AltRight
+        Minus Equal BracketLeft BracketRight Semicolon Quote Comma Period Slash
+        Backslash Backquote
+        Numpad0 Numpad1 Numpad2 Numpad3 Numpad4 Numpad5 Numpad6 Numpad7
+        Numpad8 Numpad9 NumpadAdd NumpadSubtract NumpadEnter
+    )
+}
+
+/// What a `Game` may do to the frame being built. Draws are queued and
+/// recorded into the frame's command buffer once `render` returns.
+pub struct RenderContext<'a> {
+    base_config: &'a mut BaseConfig,
+}
+
+impl<'a> RenderContext<'a> {
+    pub(super) fn new(base_config: &'a mut BaseConfig) -> Self {
+        Self { base_config }
+    }
+
+    pub fn register_material(&mut self, desc: MaterialDesc) -> Result<MaterialId, Error> {
+        self.base_config.register_material(desc)
+    }
+
+    pub fn register_named_material(
+        &mut self,
+        name: &str,
+        desc: MaterialDesc,
+    ) -> Result<MaterialId, Error> {
+        self.base_config.register_named_material(name, desc)
+    }
+
+    /// Looks up a material registered by name, including those passed to
+    /// `Application::with_material`.
+    pub fn material(&self, name: &str) -> Option<MaterialId> {
+        self.base_config.material(name)
+    }
+
+    /// Adds a texture to the bindless array at set 0, binding 0 and returns
+    /// its index there. See `BaseConfig::register_texture`.
+    pub fn register_texture(
+        &mut self,
+        image_view: ImageView,
+        sampler: Sampler,
+    ) -> Result<u32, Error> {
+        self.base_config.register_texture(image_view, sampler)
+    }
+
+    /// Color the frame starts from, as linear RGBA. Kept for later frames.
+    pub fn clear_color(&mut self, rgba: [f32; 4]) {
+        self.base_config.set_clear_color(rgba);
+    }
+
+    pub fn draw(&mut self, material: MaterialId, vertex_count: u32) {
+        self.base_config.draw(material, vertex_count);
+    }
+
+    pub fn draw_with_push_constants(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+    ) {
+        self.base_config
+            .draw_with_push_constants(material, vertex_count, push_constants);
+    }
+
+    /// Queues a draw with its own depth bias. Fails unless the material was
+    /// registered with `dynamic_depth_bias`.
+    pub fn draw_with_depth_bias(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        depth_bias: DepthBias,
+    ) -> Result<(), Error> {
+        self.base_config
+            .draw_with_depth_bias(material, vertex_count, push_constants, depth_bias)
+    }
+
+    /// Queues a draw with its own line width, for materials registered with
+    /// `dynamic_line_width`. Others keep their `line_width`.
+    pub fn draw_with_line_width(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        line_width: f32,
+    ) {
+        self.base_config
+            .draw_with_line_width(material, vertex_count, push_constants, line_width);
+    }
+
+    /// Queues a draw of a blending material, sorted back to front by
+    /// `camera_distance` among the other transparent draws.
+    pub fn draw_transparent(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        camera_distance: f32,
+    ) {
+        self.base_config
+            .draw_transparent(material, vertex_count, push_constants, camera_distance);
+    }
+
+    /// Queues a draw the GPU skips when the 32-bit value at `offset` in
+    /// `predicate` is zero. See `create_predicate_buffer`.
+    pub fn draw_conditional(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        predicate: &MappedBuffer,
+        offset: DeviceSize,
+    ) {
+        self.base_config.draw_conditional(
+            material,
+            vertex_count,
+            push_constants,
+            predicate,
+            offset,
+        );
+    }
+
+    /// A mapped buffer of `count` predicates for `draw_conditional`. Each
+    /// frame in flight should use its own range.
+    pub fn create_predicate_buffer(&self, count: u64) -> MappedBuffer {
+        self.base_config.create_predicate_buffer(count)
+    }
+
+    /// Queues a draw reading element `index` of `uniforms` through
+    /// `layout(set = 1, binding = 0) uniform`. The buffer must outlive the
+    /// frame.
+    pub fn draw_with_uniforms(
+        &mut self,
+        material: MaterialId,
+        vertex_count: u32,
+        push_constants: &[u8],
+        uniforms: &DynamicUniformBuffer,
+        index: usize,
+    ) {
+        self.base_config.draw_with_uniforms(
+            material,
+            vertex_count,
+            push_constants,
+            uniforms,
+            index,
+        );
+    }
+
+    /// A uniform buffer of `len` elements of `element_size` bytes each, for
+    /// `draw_with_uniforms`.
+    pub fn create_dynamic_uniform_buffer(
+        &self,
+        element_size: DeviceSize,
+        len: usize,
+    ) -> DynamicUniformBuffer {
+        self.base_config
+            .create_dynamic_uniform_buffer(element_size, len)
+    }
+
+    /// A buffer with a copy per frame in flight, for data rewritten every
+    /// frame. See `update_buffer_deferred`.
+    pub fn create_per_frame_buffer(
+        &self,
+        size: DeviceSize,
+        usage: BufferUsageFlags,
+    ) -> PerFrameBuffer {
+        self.base_config.create_per_frame_buffer(size, usage)
+    }
+
+    /// Writes `data` to this frame's copy of `buffer`, waiting for the GPU
+    /// to release it first.
+    pub fn update_buffer_deferred(
+        &mut self,
+        buffer: &mut PerFrameBuffer,
+        data: &[u8],
+    ) -> Result<(), EngineError> {
+        self.base_config.update_buffer_deferred(buffer, data)
+    }
+
+    pub fn extent(&self) -> Extent2D {
+        self.base_config.swapchain_extent()
+    }
+
+    /// Whether materials can use `MaterialDesc::stencil`.
+    pub fn stencil_supported(&self) -> bool {
+        self.base_config.stencil_supported()
+    }
+
+    /// Whether shaders have to output sRGB encoded color rather than linear.
+    pub fn shader_encodes_srgb(&self) -> bool {
+        self.base_config.shader_encodes_srgb()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Action {
+        Jump,
+        Confirm,
+        Fire,
+    }
+
+    impl FromStr for Action {
+        type Err = ();
+
+        fn from_str(name: &str) -> Result<Self, ()> {
+            match name {
+                "Jump" => Ok(Action::Jump),
+                "Confirm" => Ok(Action::Confirm),
+                "Fire" => Ok(Action::Fire),
+                _ => Err(()),
+            }
+        }
+    }
+
+    fn pressed(key_codes: &[KeyCode]) -> InputState {
+        let mut input = InputState::default();
+        for &key_code in key_codes {
+            input.set_pressed(key_code, true);
+        }
+        input
+    }
+
+    #[test]
+    fn rebind_replaces_every_key_of_the_action() {
+        let mut input_map = InputMap::new();
+        input_map
+            .bind(Action::Jump, KeyCode::Space)
+            .bind(Action::Jump, KeyCode::KeyW)
+            .rebind(Action::Jump, KeyCode::ArrowUp);
+        assert_eq!(input_map.keys(Action::Jump), [KeyCode::ArrowUp]);
+
+        assert!(!pressed(&[KeyCode::Space]).is_action_pressed(&input_map, Action::Jump));
+        assert!(!pressed(&[KeyCode::KeyW]).is_action_pressed(&input_map, Action::Jump));
+        assert!(pressed(&[KeyCode::ArrowUp]).is_action_pressed(&input_map, Action::Jump));
+    }
+
+    #[test]
+    fn bind_adds_keys_once() {
+        let mut input_map = InputMap::new();
+        input_map
+            .bind(Action::Fire, KeyCode::KeyF)
+            .bind(Action::Fire, KeyCode::KeyF)
+            .bind(Action::Fire, KeyCode::Enter);
+        assert_eq!(
+            input_map.keys(Action::Fire),
+            [KeyCode::KeyF, KeyCode::Enter]
+        );
+    }
+
+    #[test]
+    fn one_key_can_trigger_several_actions() {
+        let mut input_map = InputMap::new();
+        input_map
+            .bind(Action::Jump, KeyCode::Space)
+            .bind(Action::Confirm, KeyCode::Space)
+            .bind(Action::Confirm, KeyCode::Enter);
+        let input = pressed(&[KeyCode::Space]);
+        assert!(input.is_action_pressed(&input_map, Action::Jump));
+        assert!(input.is_action_pressed(&input_map, Action::Confirm));
+        assert!(!input.is_action_pressed(&input_map, Action::Fire));
+
+        // Rebinding one action leaves the key on the other.
+        input_map.rebind(Action::Jump, KeyCode::KeyW);
+        assert!(!input.is_action_pressed(&input_map, Action::Jump));
+        assert!(input.is_action_pressed(&input_map, Action::Confirm));
+    }
+
+    #[test]
+    fn unbound_actions_have_no_keys() {
+        let mut input_map = InputMap::new();
+        input_map.bind(Action::Fire, KeyCode::KeyF);
+        input_map.unbind(Action::Fire);
+        assert!(input_map.keys(Action::Fire).is_empty());
+        assert!(!pressed(&[KeyCode::KeyF]).is_action_pressed(&input_map, Action::Fire));
+    }
+
+    #[test]
+    fn config_binds_every_listed_key() {
+        let input_map = InputMap::from_config(
+            "# Movement\n\
+             Jump = Space\n\
+             \n\
+             Jump=KeyW\n  \
+             Confirm = Enter\n\
+             Confirm = Space\n",
+        )
+        .unwrap();
+        assert_eq!(
+            input_map.keys(Action::Jump),
+            [KeyCode::Space, KeyCode::KeyW]
+        );
+        assert_eq!(
+            input_map.keys(Action::Confirm),
+            [KeyCode::Enter, KeyCode::Space]
+        );
+        assert!(input_map.keys(Action::Fire).is_empty());
+
+        let input = pressed(&[KeyCode::Space]);
+        assert!(input.is_action_pressed(&input_map, Action::Jump));
+        assert!(input.is_action_pressed(&input_map, Action::Confirm));
+    }
+
+    #[test]
+    fn config_reports_unknown_keys() {
+        let err = InputMap::<Action>::from_config("Jump = Space\nFire = Spacebar").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), r#"Line 2: unknown key "Spacebar""#);
+    }
+
+    #[test]
+    fn config_reports_unknown_actions_and_malformed_lines() {
+        let err = InputMap::<Action>::from_config("Crouch = KeyC").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), r#"Line 1: unknown action "Crouch""#);
+
+        let err = InputMap::<Action>::from_config("# Keys\nJump Space").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .starts_with("Line 2: expected `action = KeyCode`"));
+    }
+}
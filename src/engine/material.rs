@@ -0,0 +1,600 @@
+use std::{fs::File, io::Error, path::PathBuf, sync::Arc};
+
+use ash::{
+    util::read_spv,
+    vk::{
+        self, BlendFactor, BlendOp, ColorComponentFlags, CompareOp, CullModeFlags, DynamicState,
+        FrontFace, GraphicsPipelineCreateInfo, Pipeline, PipelineCache,
+        PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo, PipelineCreateFlags,
+        PipelineDepthStencilStateCreateInfo, PipelineDynamicStateCreateInfo,
+        PipelineInputAssemblyStateCreateInfo, PipelineLayout, PipelineMultisampleStateCreateInfo,
+        PipelineRasterizationStateCreateInfo, PipelineShaderStageCreateInfo,
+        PipelineTessellationStateCreateInfo, PipelineVertexInputStateCreateInfo,
+        PipelineViewportStateCreateInfo, PolygonMode, PrimitiveTopology, RenderPass,
+        SampleCountFlags, ShaderStageFlags, SpecializationInfo, SpecializationMapEntry, StencilOp,
+        StencilOpState,
+    },
+};
+
+use super::{
+    device_context::DeviceContext,
+    resource::{PipelineGuard, ShaderModuleGuard},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+
+impl MaterialId {
+    pub(super) fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    pub(super) fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    Alpha,
+    Additive,
+}
+
+/// Specialization constant the engine sets on every material: a bool that is
+/// true when the swapchain is UNORM, so fragment shaders have to apply the
+/// sRGB transfer function themselves, and false when the hardware encodes
+/// their linear output. Declare it in GLSL as
+/// `layout(constant_id = 1000) const bool ENCODE_SRGB = false;`.
+pub const ENCODE_SRGB_CONSTANT_ID: u32 = 1000;
+
+/// A specialization constant value. Each one occupies 4 bytes; booleans are
+/// passed as `VkBool32`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpecConstant {
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+}
+
+impl SpecConstant {
+    fn to_ne_bytes(self) -> [u8; 4] {
+        match self {
+            SpecConstant::Bool(value) => vk::Bool32::from(value).to_ne_bytes(),
+            SpecConstant::Int(value) => value.to_ne_bytes(),
+            SpecConstant::UInt(value) => value.to_ne_bytes(),
+            SpecConstant::Float(value) => value.to_ne_bytes(),
+        }
+    }
+}
+
+/// Depth offset applied to rasterized polygons, in the units of
+/// `vkCmdSetDepthBias`, e.g. to keep shadow maps from self-shadowing or
+/// decals from z-fighting.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    /// Largest bias applied, or 0.0 for no limit. Other values need the
+    /// `depth_bias_clamp` device feature.
+    pub clamp: f32,
+    pub slope_factor: f32,
+}
+
+/// Stencil test and update of a material, the same for front and back faces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StencilDesc {
+    /// How the stored value compares to `reference` for the test to pass.
+    pub compare_op: CompareOp,
+    /// Value compared against, and written by `StencilOp::REPLACE`.
+    pub reference: u32,
+    /// Applied where the stencil test passes.
+    pub pass_op: StencilOp,
+    /// Applied where the stencil test fails.
+    pub fail_op: StencilOp,
+}
+
+impl StencilDesc {
+    /// Marks everything the material draws with `reference`, e.g. the
+    /// pixels of a selected mesh.
+    pub fn write(reference: u32) -> Self {
+        Self {
+            compare_op: CompareOp::ALWAYS,
+            reference,
+            pass_op: StencilOp::REPLACE,
+            fail_op: StencilOp::KEEP,
+        }
+    }
+
+    /// Draws only where the stencil does not hold `reference` and leaves it
+    /// as is, e.g. an outline around what `write` marked.
+    pub fn not_equal(reference: u32) -> Self {
+        Self {
+            compare_op: CompareOp::NOT_EQUAL,
+            reference,
+            pass_op: StencilOp::KEEP,
+            fail_op: StencilOp::KEEP,
+        }
+    }
+
+    fn op_state(self) -> StencilOpState {
+        StencilOpState {
+            fail_op: self.fail_op,
+            pass_op: self.pass_op,
+            // Fragments hidden by the depth test leave the stencil as is.
+            depth_fail_op: StencilOp::KEEP,
+            compare_op: self.compare_op,
+            compare_mask: !0,
+            write_mask: !0,
+            reference: self.reference,
+        }
+    }
+}
+
+/// Tessellation stages of a material. The subdivision level is up to the
+/// control shader, e.g. read from push constants to change it at runtime.
+#[derive(Clone, Debug)]
+pub struct TessellationDesc {
+    pub control_shader: PathBuf,
+    pub evaluation_shader: PathBuf,
+    /// Vertices per patch, e.g. 4 for a quad.
+    pub patch_control_points: u32,
+}
+
+/// Everything needed to build the graphics pipeline of a material. Shader
+/// paths point at compiled SPIR-V.
+#[derive(Clone, Debug)]
+pub struct MaterialDesc {
+    pub vertex_shader: PathBuf,
+    pub fragment_shader: PathBuf,
+    /// Optional geometry stage between the vertex and fragment shaders.
+    /// Needs the `geometry_shader` device feature.
+    pub geometry_shader: Option<PathBuf>,
+    /// Optional tessellation stages. Needs the `tessellation_shader` device
+    /// feature and `PrimitiveTopology::PATCH_LIST`.
+    pub tessellation: Option<TessellationDesc>,
+    /// Opaque materials test and write depth. Blending ones only test it, so
+    /// what lies behind them still shows through other transparent draws.
+    pub blend_mode: BlendMode,
+    /// Faces to discard: `NONE` for double-sided geometry such as foliage,
+    /// `FRONT` for geometry seen from inside such as a skybox.
+    pub cull_mode: CullModeFlags,
+    /// Winding of front faces as they appear on screen. Y-up geometry wound
+    /// counter-clockwise from outside, like `primitives`, keeps that winding
+    /// under a projection that flips Y into Vulkan's Y-down clip space, so
+    /// the default suits it as well as the built-in shaders.
+    pub front_face: FrontFace,
+    pub topology: PrimitiveTopology,
+    /// Width of rasterized lines. Clamped to what the device supports, which
+    /// is only 1.0 without the `wide_lines` feature.
+    pub line_width: f32,
+    /// Makes line width dynamic state, so each draw can set its own with
+    /// `draw_with_line_width`, starting from `line_width`. Dropped without
+    /// `wide_lines`, since lines are 1.0 wide regardless then.
+    pub dynamic_line_width: bool,
+    /// Bias baked into the pipeline, or the initial value when
+    /// `dynamic_depth_bias` is set. `None` disables biasing.
+    pub depth_bias: Option<DepthBias>,
+    /// Makes depth bias dynamic state, so each draw can set its own with
+    /// `draw_with_depth_bias` without a pipeline per value.
+    pub dynamic_depth_bias: bool,
+    /// Builds the pipeline as a derivative of another material's, which some
+    /// drivers create faster for variants differing in little state. Drivers
+    /// are free to ignore this.
+    pub derive_from: Option<MaterialId>,
+    /// Stencil test and update against the frame's stencil attachment,
+    /// cleared to 0 every frame. See `BaseConfig::stencil_supported`.
+    pub stencil: Option<StencilDesc>,
+    /// `(constant_id, value)` pairs applied to every shader stage, so one
+    /// SPIR-V file can be built into several variants.
+    pub specialization_constants: Vec<(u32, SpecConstant)>,
+}
+
+impl MaterialDesc {
+    /// Blending materials depend on what was drawn before them, so they are
+    /// drawn after all opaque ones and sorted back to front.
+    pub fn is_transparent(&self) -> bool {
+        self.blend_mode != BlendMode::Opaque
+    }
+}
+
+impl Default for MaterialDesc {
+    fn default() -> Self {
+        Self {
+            vertex_shader: PathBuf::new(),
+            fragment_shader: PathBuf::new(),
+            geometry_shader: None,
+            tessellation: None,
+            blend_mode: BlendMode::Opaque,
+            cull_mode: CullModeFlags::BACK,
+            front_face: FrontFace::COUNTER_CLOCKWISE,
+            topology: PrimitiveTopology::TRIANGLE_LIST,
+            line_width: 1.0,
+            dynamic_line_width: false,
+            depth_bias: None,
+            dynamic_depth_bias: false,
+            derive_from: None,
+            stencil: None,
+            specialization_constants: Vec::new(),
+        }
+    }
+}
+
+/// A registered material: its description plus the SPIR-V read at
+/// registration time, so lazy pipeline creation cannot fail on I/O.
+pub(super) struct Material {
+    pub desc: MaterialDesc,
+    vertex_code: Vec<u32>,
+    fragment_code: Vec<u32>,
+    geometry_code: Option<Vec<u32>>,
+    /// Control and evaluation shader code.
+    tessellation_code: Option<(Vec<u32>, Vec<u32>)>,
+}
+
+impl Material {
+    pub fn load(desc: MaterialDesc) -> Result<Self, Error> {
+        let vertex_code = read_spv(&mut File::open(&desc.vertex_shader)?)?;
+        let fragment_code = read_spv(&mut File::open(&desc.fragment_shader)?)?;
+        let geometry_code = desc
+            .geometry_shader
+            .as_ref()
+            .map(|path| read_spv(&mut File::open(path)?))
+            .transpose()?;
+        let tessellation_code = match &desc.tessellation {
+            Some(tessellation) => Some((
+                read_spv(&mut File::open(&tessellation.control_shader)?)?,
+                read_spv(&mut File::open(&tessellation.evaluation_shader)?)?,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            desc,
+            vertex_code,
+            fragment_code,
+            geometry_code,
+            tessellation_code,
+        })
+    }
+}
+
+fn color_blend_attachment(blend_mode: BlendMode) -> PipelineColorBlendAttachmentState {
+    let attachment =
+        PipelineColorBlendAttachmentState::default().color_write_mask(ColorComponentFlags::RGBA);
+    let (src_color, dst_color) = match blend_mode {
+        BlendMode::Opaque => return attachment.blend_enable(false),
+        BlendMode::Alpha => (BlendFactor::SRC_ALPHA, BlendFactor::ONE_MINUS_SRC_ALPHA),
+        BlendMode::Additive => (BlendFactor::ONE, BlendFactor::ONE),
+    };
+
+    attachment
+        .blend_enable(true)
+        .src_color_blend_factor(src_color)
+        .dst_color_blend_factor(dst_color)
+        .color_blend_op(BlendOp::ADD)
+        .src_alpha_blend_factor(BlendFactor::ONE)
+        .dst_alpha_blend_factor(BlendFactor::ZERO)
+        .alpha_blend_op(BlendOp::ADD)
+}
+
+/// `line_width` clamped to the device's range and rounded to its
+/// granularity, or 1.0 without `wide_lines`.
+pub(super) fn clamp_line_width(context: &DeviceContext, line_width: f32) -> f32 {
+    let (features, properties) = unsafe {
+        (
+            context
+                .instance
+                .get_physical_device_features(context.physical_device),
+            context
+                .instance
+                .get_physical_device_properties(context.physical_device),
+        )
+    };
+    if features.wide_lines == vk::FALSE {
+        return 1.0;
+    }
+    let [min, max] = properties.limits.line_width_range;
+    let granularity = properties.limits.line_width_granularity;
+    let line_width = line_width.clamp(min, max);
+    if granularity > 0.0 {
+        (min + ((line_width - min) / granularity).round() * granularity).min(max)
+    } else {
+        line_width
+    }
+}
+
+pub(super) fn supported_line_width(context: &DeviceContext, line_width: f32) -> f32 {
+    let supported = clamp_line_width(context, line_width);
+    if supported != line_width {
+        println!("Line width {line_width} is not supported, using {supported}");
+    }
+    supported
+}
+
+/// Rasterization state of `desc`, with lines `line_width` wide. Depth bias
+/// is enabled for a static bias and for dynamic bias, whose value the
+/// draws set.
+fn rasterization_state(
+    desc: &MaterialDesc,
+    line_width: f32,
+) -> PipelineRasterizationStateCreateInfo<'static> {
+    let depth_bias = desc.depth_bias.unwrap_or_default();
+    PipelineRasterizationStateCreateInfo::default()
+        .polygon_mode(PolygonMode::FILL)
+        .line_width(line_width)
+        .cull_mode(desc.cull_mode)
+        .front_face(desc.front_face)
+        .depth_bias_enable(desc.depth_bias.is_some() || desc.dynamic_depth_bias)
+        .depth_bias_constant_factor(depth_bias.constant_factor)
+        .depth_bias_clamp(depth_bias.clamp)
+        .depth_bias_slope_factor(depth_bias.slope_factor)
+}
+
+/// Depth and stencil state of `desc` in a subpass with or without a depth
+/// attachment. Depth is tested with `LESS_OR_EQUAL`, so draws at equal depth
+/// land in draw order, and written by opaque materials only.
+fn depth_stencil_state(
+    desc: &MaterialDesc,
+    depth_attachment: bool,
+) -> PipelineDepthStencilStateCreateInfo<'static> {
+    let stencil = desc.stencil.map(StencilDesc::op_state);
+    PipelineDepthStencilStateCreateInfo::default()
+        .depth_test_enable(depth_attachment)
+        .depth_write_enable(depth_attachment && !desc.is_transparent())
+        .depth_compare_op(CompareOp::LESS_OR_EQUAL)
+        .stencil_test_enable(stencil.is_some())
+        .front(stencil.unwrap_or_default())
+        .back(stencil.unwrap_or_default())
+}
+
+/// Builds the pipeline for `material`. Viewport and scissor are dynamic so
+/// the pipeline survives swapchain recreation. `depth_attachment` tells
+/// whether subpass 0 of `render_pass` has one to test against.
+/// `allow_derivatives` lets later pipelines name this one as their
+/// `base_pipeline`, which must have been created that way.
+pub(super) fn create_graphics_pipeline(
+    context: &Arc<DeviceContext>,
+    pipeline_cache: PipelineCache,
+    (render_pass, depth_attachment): (RenderPass, bool),
+    pipeline_layout: PipelineLayout,
+    material: &Material,
+    allow_derivatives: bool,
+    base_pipeline: Option<Pipeline>,
+) -> PipelineGuard {
+    // The modules are only needed while the pipeline is being built.
+    let vertex_module = ShaderModuleGuard::new(context, &material.vertex_code);
+    let fragment_module = ShaderModuleGuard::new(context, &material.fragment_code);
+    let geometry_module = material
+        .geometry_code
+        .as_ref()
+        .map(|code| ShaderModuleGuard::new(context, code));
+    let tessellation_modules =
+        material
+            .tessellation_code
+            .as_ref()
+            .map(|(control_code, evaluation_code)| {
+                (
+                    ShaderModuleGuard::new(context, control_code),
+                    ShaderModuleGuard::new(context, evaluation_code),
+                )
+            });
+    unsafe {
+        let mut specialization_data = Vec::new();
+        let mut specialization_map_entries = Vec::new();
+        for &(constant_id, value) in &material.desc.specialization_constants {
+            specialization_map_entries.push(
+                SpecializationMapEntry::default()
+                    .constant_id(constant_id)
+                    .offset(specialization_data.len() as u32)
+                    .size(4),
+            );
+            specialization_data.extend_from_slice(&value.to_ne_bytes());
+        }
+        let specialization_info = SpecializationInfo::default()
+            .map_entries(&specialization_map_entries)
+            .data(&specialization_data);
+
+        let mut shader_stages = vec![
+            PipelineShaderStageCreateInfo::default()
+                .stage(ShaderStageFlags::VERTEX)
+                .module(vertex_module.handle())
+                .name(c"main")
+                .specialization_info(&specialization_info),
+            PipelineShaderStageCreateInfo::default()
+                .stage(ShaderStageFlags::FRAGMENT)
+                .module(fragment_module.handle())
+                .name(c"main")
+                .specialization_info(&specialization_info),
+        ];
+        if let Some(geometry_module) = &geometry_module {
+            shader_stages.push(
+                PipelineShaderStageCreateInfo::default()
+                    .stage(ShaderStageFlags::GEOMETRY)
+                    .module(geometry_module.handle())
+                    .name(c"main")
+                    .specialization_info(&specialization_info),
+            );
+        }
+        if let Some((control_module, evaluation_module)) = &tessellation_modules {
+            shader_stages.push(
+                PipelineShaderStageCreateInfo::default()
+                    .stage(ShaderStageFlags::TESSELLATION_CONTROL)
+                    .module(control_module.handle())
+                    .name(c"main")
+                    .specialization_info(&specialization_info),
+            );
+            shader_stages.push(
+                PipelineShaderStageCreateInfo::default()
+                    .stage(ShaderStageFlags::TESSELLATION_EVALUATION)
+                    .module(evaluation_module.handle())
+                    .name(c"main")
+                    .specialization_info(&specialization_info),
+            );
+        }
+        let tessellation_state = PipelineTessellationStateCreateInfo::default()
+            .patch_control_points(
+                material
+                    .desc
+                    .tessellation
+                    .as_ref()
+                    .map_or(0, |tessellation| tessellation.patch_control_points),
+            );
+
+        let vertex_input_state = PipelineVertexInputStateCreateInfo::default();
+        let input_assembly_state = PipelineInputAssemblyStateCreateInfo::default()
+            .topology(material.desc.topology)
+            .primitive_restart_enable(false);
+        let viewport_state = PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterization_state = rasterization_state(
+            &material.desc,
+            supported_line_width(context, material.desc.line_width),
+        );
+        let multisample_state = PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(SampleCountFlags::TYPE_1)
+            .sample_shading_enable(false);
+        let depth_stencil_state = depth_stencil_state(&material.desc, depth_attachment);
+        let color_blend_attachments = [color_blend_attachment(material.desc.blend_mode)];
+        let color_blend_state = PipelineColorBlendStateCreateInfo::default()
+            .logic_op_enable(false)
+            .attachments(&color_blend_attachments);
+        let mut dynamic_states = vec![DynamicState::VIEWPORT, DynamicState::SCISSOR];
+        if material.desc.dynamic_depth_bias {
+            dynamic_states.push(DynamicState::DEPTH_BIAS);
+        }
+        if material.desc.dynamic_line_width {
+            dynamic_states.push(DynamicState::LINE_WIDTH);
+        }
+        let dynamic_state =
+            PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let mut flags = PipelineCreateFlags::empty();
+        if allow_derivatives {
+            flags |= PipelineCreateFlags::ALLOW_DERIVATIVES;
+        }
+        if base_pipeline.is_some() {
+            flags |= PipelineCreateFlags::DERIVATIVE;
+        }
+        let pipeline_create_info = GraphicsPipelineCreateInfo::default()
+            .flags(flags)
+            .base_pipeline_handle(base_pipeline.unwrap_or_default())
+            .base_pipeline_index(-1)
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .tessellation_state(&tessellation_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0);
+
+        let pipeline = context
+            .device
+            .create_graphics_pipelines(pipeline_cache, &[pipeline_create_info], None)
+            .map_err(|(_, err)| err)
+            .expect("Failed to create graphics pipeline")[0];
+
+        PipelineGuard::new(context, pipeline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stencil_write_marks_every_fragment() {
+        let state = StencilDesc::write(3).op_state();
+        assert_eq!(state.compare_op, CompareOp::ALWAYS);
+        assert_eq!(state.pass_op, StencilOp::REPLACE);
+        assert_eq!(state.reference, 3);
+        assert_eq!(state.write_mask, !0);
+    }
+
+    #[test]
+    fn stencil_not_equal_leaves_the_stencil_alone() {
+        let state = StencilDesc::not_equal(3).op_state();
+        assert_eq!(state.compare_op, CompareOp::NOT_EQUAL);
+        assert_eq!(state.reference, 3);
+        assert_eq!(
+            [state.pass_op, state.fail_op, state.depth_fail_op],
+            [StencilOp::KEEP; 3]
+        );
+        assert_eq!(state.compare_mask, !0);
+    }
+
+    #[test]
+    fn opaque_materials_test_and_write_depth() {
+        let state = depth_stencil_state(&MaterialDesc::default(), true);
+        assert_eq!(state.depth_test_enable, vk::TRUE);
+        assert_eq!(state.depth_write_enable, vk::TRUE);
+        assert_eq!(state.depth_compare_op, CompareOp::LESS_OR_EQUAL);
+    }
+
+    #[test]
+    fn blending_materials_only_test_depth() {
+        for blend_mode in [BlendMode::Alpha, BlendMode::Additive] {
+            let desc = MaterialDesc {
+                blend_mode,
+                ..Default::default()
+            };
+            let state = depth_stencil_state(&desc, true);
+            assert_eq!(state.depth_test_enable, vk::TRUE);
+            assert_eq!(state.depth_write_enable, vk::FALSE);
+        }
+    }
+
+    #[test]
+    fn biased_materials_test_and_write_the_depth_they_offset() {
+        let bias = DepthBias {
+            constant_factor: 1.25,
+            clamp: 0.0,
+            slope_factor: 1.75,
+        };
+        let static_bias = MaterialDesc {
+            depth_bias: Some(bias),
+            ..Default::default()
+        };
+        let dynamic_bias = MaterialDesc {
+            dynamic_depth_bias: true,
+            ..Default::default()
+        };
+        for desc in [static_bias, dynamic_bias] {
+            let rasterization = rasterization_state(&desc, 1.0);
+            assert_eq!(rasterization.depth_bias_enable, vk::TRUE);
+            let depth_stencil = depth_stencil_state(&desc, true);
+            assert_eq!(depth_stencil.depth_test_enable, vk::TRUE);
+            assert_eq!(depth_stencil.depth_write_enable, vk::TRUE);
+        }
+
+        let rasterization = rasterization_state(
+            &MaterialDesc {
+                depth_bias: Some(bias),
+                ..Default::default()
+            },
+            1.0,
+        );
+        assert_eq!(rasterization.depth_bias_constant_factor, 1.25);
+        assert_eq!(rasterization.depth_bias_slope_factor, 1.75);
+        let unbiased = rasterization_state(&MaterialDesc::default(), 1.0);
+        assert_eq!(unbiased.depth_bias_enable, vk::FALSE);
+    }
+
+    #[test]
+    fn no_depth_state_without_an_attachment() {
+        let desc = MaterialDesc {
+            stencil: Some(StencilDesc::write(1)),
+            ..Default::default()
+        };
+        let state = depth_stencil_state(&desc, false);
+        assert_eq!(state.depth_test_enable, vk::FALSE);
+        assert_eq!(state.depth_write_enable, vk::FALSE);
+        assert_eq!(state.stencil_test_enable, vk::TRUE);
+    }
+}
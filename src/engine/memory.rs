@@ -0,0 +1,18 @@
+use ash::vk::{MemoryPropertyFlags, PhysicalDeviceMemoryProperties};
+
+/// Index of the first memory type allowed by `type_bits` (from
+/// `MemoryRequirements::memory_type_bits`) that has all of `flags`.
+pub fn find_memory_type_index(
+    memory_properties: &PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    flags: MemoryPropertyFlags,
+) -> Option<u32> {
+    memory_properties
+        .memory_types_as_slice()
+        .iter()
+        .enumerate()
+        .find(|(index, memory_type)| {
+            type_bits & (1 << index) != 0 && memory_type.property_flags.contains(flags)
+        })
+        .map(|(index, _)| index as u32)
+}
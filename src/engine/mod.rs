@@ -1,2 +1,21 @@
+pub mod adapter;
 pub mod app;
 mod base_configuration;
+mod benchmark;
+mod bindless;
+pub mod buffer_pool;
+pub mod deletion_queue;
+pub mod descriptor;
+pub mod device_context;
+pub mod error;
+pub mod frame_timer;
+pub mod game;
+pub mod material;
+mod memory;
+mod overlay;
+pub mod parallel;
+pub mod primitives;
+pub mod render_target;
+pub mod resource;
+pub mod sync;
+pub mod vertex;
@@ -0,0 +1,112 @@
+use ash::vk::CullModeFlags;
+
+use super::{
+    base_configuration::BaseConfig,
+    material::{BlendMode, MaterialDesc, MaterialId},
+};
+
+/// Characters that fit in the push constants of `shader/overlay.vert`.
+const MAX_LINE_LENGTH: usize = 112;
+/// Logical pixels per font pixel. A character cell is 4x6 font pixels.
+const FONT_SCALE: f32 = 2.0;
+const MARGIN_CELLS: f32 = 1.0;
+
+/// Diagnostics text drawn on top of the scene: the adapter name, its MSAA
+/// limit and the CPU and GPU frame times. Glyphs come from a bitmap font in
+/// the fragment shader, so the overlay needs no textures and writes no depth.
+pub(super) struct DebugOverlay {
+    material: Option<MaterialId>,
+    visible: bool,
+}
+
+impl DebugOverlay {
+    pub fn new() -> Self {
+        Self {
+            material: None,
+            visible: false,
+        }
+    }
+
+    pub fn init(&mut self, base_config: &mut BaseConfig) {
+        self.material = base_config
+            .register_material(MaterialDesc {
+                vertex_shader: "shader/overlay.vert.spv".into(),
+                fragment_shader: "shader/overlay.frag.spv".into(),
+                // Transparent draws go last, and at distance zero the overlay
+                // stays on top of them too. Glyphs are fully opaque anyway.
+                blend_mode: BlendMode::Alpha,
+                cull_mode: CullModeFlags::NONE,
+                ..Default::default()
+            })
+            .inspect_err(|err| {
+                println!("Overlay shaders unavailable ({err}), run shader/compile.sh")
+            })
+            .ok();
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Queues the overlay after everything drawn so far this frame. The text
+    /// is sized in logical pixels, so it stays readable on HiDPI displays.
+    pub fn draw(&self, base_config: &mut BaseConfig, cpu_frame_ms: f32, scale_factor: f32) {
+        let Some(material) = self.material.filter(|_| self.visible) else {
+            return;
+        };
+        let gpu_frame = match base_config.last_gpu_frame_ms() {
+            Some(gpu_frame_ms) => format!("{gpu_frame_ms:.2} MS"),
+            None => "N/A".to_owned(),
+        };
+        let lines = [
+            base_config.adapter_name(),
+            format!("MSAA {}X", base_config.max_usable_sample_count().as_raw()),
+            format!("CPU {cpu_frame_ms:.2} MS"),
+            format!("GPU {gpu_frame}"),
+        ];
+
+        let extent = base_config.swapchain_extent();
+        let font_scale = (FONT_SCALE * scale_factor).round().max(1.0);
+        let cell_size = [
+            4.0 * font_scale * 2.0 / extent.width as f32,
+            6.0 * font_scale * 2.0 / extent.height as f32,
+        ];
+        // With Y pointing up, the text is laid out from the top edge downwards
+        // all the same.
+        let y_sign = if base_config.viewport_flipped() {
+            -1.0
+        } else {
+            1.0
+        };
+        for (line_index, line) in lines.iter().enumerate() {
+            let origin = [
+                -1.0 + MARGIN_CELLS * cell_size[0],
+                y_sign * (-1.0 + (MARGIN_CELLS + line_index as f32) * cell_size[1]),
+            ];
+            let (text, length) = pack_line(line);
+            let mut push_constants = Vec::with_capacity(16 + text.len());
+            for value in origin.iter().chain(&[cell_size[0], y_sign * cell_size[1]]) {
+                push_constants.extend_from_slice(&value.to_ne_bytes());
+            }
+            push_constants.extend_from_slice(&text);
+            base_config.draw_with_push_constants(material, 6 * length as u32, &push_constants);
+        }
+    }
+}
+
+/// Packs `line` as the shader expects it: upper-cased ASCII bytes, anything
+/// the font lacks replaced by `?`. Returns the bytes and the character count.
+fn pack_line(line: &str) -> ([u8; MAX_LINE_LENGTH], usize) {
+    let mut text = [0; MAX_LINE_LENGTH];
+    let mut length = 0;
+    for (slot, character) in text.iter_mut().zip(line.chars()) {
+        let character = character.to_ascii_uppercase();
+        *slot = if (' '..='_').contains(&character) {
+            character as u8
+        } else {
+            b'?'
+        };
+        length += 1;
+    }
+    (text, length)
+}
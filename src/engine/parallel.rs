@@ -0,0 +1,141 @@
+use std::{sync::Arc, thread};
+
+use ash::vk::{
+    CommandBuffer, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferInheritanceInfo,
+    CommandBufferLevel, CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags,
+    CommandPoolCreateInfo, CommandPoolResetFlags, Framebuffer, RenderPass,
+};
+
+use super::device_context::DeviceContext;
+
+/// Records secondary command buffers for a render pass on several threads.
+/// Every worker has its own command pool per frame slot, since a pool may
+/// only be used from one thread at a time.
+pub struct ParallelRecorder {
+    context: Arc<DeviceContext>,
+    /// `pools[frame][worker]`, with one secondary buffer allocated from each.
+    pools: Vec<Vec<CommandPool>>,
+    command_buffers: Vec<Vec<CommandBuffer>>,
+}
+
+impl ParallelRecorder {
+    pub fn new(
+        context: &Arc<DeviceContext>,
+        queue_family_index: u32,
+        frames: usize,
+        workers: usize,
+    ) -> Self {
+        let device = &context.device;
+        let workers = workers.max(1);
+        let mut pools = Vec::with_capacity(frames);
+        let mut command_buffers = Vec::with_capacity(frames);
+        for _ in 0..frames {
+            let mut frame_pools = Vec::with_capacity(workers);
+            let mut frame_command_buffers = Vec::with_capacity(workers);
+            for _ in 0..workers {
+                unsafe {
+                    let pool = device
+                        .create_command_pool(
+                            &CommandPoolCreateInfo::default()
+                                .flags(CommandPoolCreateFlags::TRANSIENT)
+                                .queue_family_index(queue_family_index),
+                            None,
+                        )
+                        .expect("Failed to create worker command pool");
+                    let command_buffer = device
+                        .allocate_command_buffers(
+                            &CommandBufferAllocateInfo::default()
+                                .command_pool(pool)
+                                .level(CommandBufferLevel::SECONDARY)
+                                .command_buffer_count(1),
+                        )
+                        .expect("Failed to allocate secondary command buffer")[0];
+                    frame_pools.push(pool);
+                    frame_command_buffers.push(command_buffer);
+                }
+            }
+            pools.push(frame_pools);
+            command_buffers.push(frame_command_buffers);
+        }
+
+        Self {
+            context: Arc::clone(context),
+            pools,
+            command_buffers,
+        }
+    }
+
+    /// Splits `items` into one contiguous chunk per worker and records each
+    /// chunk with `record_chunk` on its own thread, inside subpass 0 of
+    /// `render_pass`. The returned buffers are meant for `cmd_execute_commands`
+    /// in a render pass begun with `SubpassContents::SECONDARY_COMMAND_BUFFERS`,
+    /// in the order given. The buffers of `frame` must no longer be in use.
+    ///
+    /// Secondary buffers inherit no dynamic state, so `record_chunk` has to
+    /// set the viewport and scissor itself. `items` can be draw closures.
+    pub fn record<T, F>(
+        &self,
+        frame: usize,
+        render_pass: RenderPass,
+        framebuffer: Framebuffer,
+        items: &[T],
+        record_chunk: F,
+    ) -> Vec<CommandBuffer>
+    where
+        T: Sync,
+        F: Fn(CommandBuffer, &[T]) + Sync,
+    {
+        let device = &self.context.device;
+        let workers = self.pools[frame].len();
+        let chunk_size = items.len().div_ceil(workers).max(1);
+        let chunks: Vec<&[T]> = items.chunks(chunk_size).collect();
+        let command_buffers = &self.command_buffers[frame][..chunks.len()];
+
+        for &pool in &self.pools[frame][..chunks.len()] {
+            unsafe {
+                device
+                    .reset_command_pool(pool, CommandPoolResetFlags::empty())
+                    .expect("Failed to reset worker command pool");
+            }
+        }
+
+        let record_chunk = &record_chunk;
+        thread::scope(|scope| {
+            for (&command_buffer, &chunk) in command_buffers.iter().zip(&chunks) {
+                scope.spawn(move || unsafe {
+                    let inheritance_info = CommandBufferInheritanceInfo::default()
+                        .render_pass(render_pass)
+                        .subpass(0)
+                        .framebuffer(framebuffer);
+                    device
+                        .begin_command_buffer(
+                            command_buffer,
+                            &CommandBufferBeginInfo::default()
+                                .flags(
+                                    CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                                        | CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+                                )
+                                .inheritance_info(&inheritance_info),
+                        )
+                        .expect("Failed to begin secondary command buffer");
+                    record_chunk(command_buffer, chunk);
+                    device
+                        .end_command_buffer(command_buffer)
+                        .expect("Failed to end secondary command buffer");
+                });
+            }
+        });
+
+        command_buffers.to_vec()
+    }
+}
+
+impl Drop for ParallelRecorder {
+    fn drop(&mut self) {
+        unsafe {
+            for &pool in self.pools.iter().flatten() {
+                self.context.device.destroy_command_pool(pool, None);
+            }
+        }
+    }
+}
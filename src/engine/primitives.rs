@@ -0,0 +1,239 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::impl_vertex;
+
+/// Vertex of the built-in meshes. Every mesh is centered on the origin with
+/// a size of one unit, in a right-handed, Y-up space. Front faces wind
+/// counter-clockwise seen from outside, which a projection flipping Y for
+/// Vulkan keeps on screen, matching the default `FrontFace::COUNTER_CLOCKWISE`.
+/// UVs put `(0, 0)` at the top left, like image data.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrimitiveVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+impl_vertex!(PrimitiveVertex {
+    position,
+    normal,
+    uv
+});
+
+/// Corners of a face in the order `face` emits them, as steps along its
+/// right and up axes, with their UVs.
+const FACE_CORNERS: [([f32; 2], [f32; 2]); 4] = [
+    ([-0.5, -0.5], [0.0, 1.0]),
+    ([0.5, -0.5], [1.0, 1.0]),
+    ([0.5, 0.5], [1.0, 0.0]),
+    ([-0.5, 0.5], [0.0, 0.0]),
+];
+
+/// A quad in the XY plane facing +Z.
+pub fn quad() -> (Vec<PrimitiveVertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(4);
+    let mut indices = Vec::with_capacity(6);
+    face(
+        &mut vertices,
+        &mut indices,
+        [0.0, 0.0, 1.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        0.0,
+    );
+    (vertices, indices)
+}
+
+/// A cube with separate vertices per face, so normals and UVs do not bleed
+/// across edges. Each face maps the whole texture.
+pub fn cube() -> (Vec<PrimitiveVertex>, Vec<u32>) {
+    // Normal, right and up axis of each face, with right x up = normal.
+    const FACES: [[[f32; 3]; 3]; 6] = [
+        [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]],
+        [[-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+        [[0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]],
+        [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+        [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        [[0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+    ];
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for [normal, right, up] in FACES {
+        face(&mut vertices, &mut indices, normal, right, up, 0.5);
+    }
+    (vertices, indices)
+}
+
+/// A sphere of diameter one with `segments` slices around the Y axis and
+/// half as many rings from pole to pole. `segments` is raised to at least 3.
+/// The seam at U = 0 and the poles have duplicated vertices so UVs stay
+/// continuous.
+pub fn uv_sphere(segments: u32) -> (Vec<PrimitiveVertex>, Vec<u32>) {
+    let segments = segments.max(3);
+    let rings = (segments / 2).max(2);
+    let mut vertices = Vec::with_capacity(((rings + 1) * (segments + 1)) as usize);
+    for ring in 0..=rings {
+        let v = ring as f32 / rings as f32;
+        let (sin_theta, cos_theta) = (v * PI).sin_cos();
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let (sin_phi, cos_phi) = (u * TAU).sin_cos();
+            let normal = [sin_theta * cos_phi, cos_theta, -sin_theta * sin_phi];
+            vertices.push(PrimitiveVertex {
+                position: normal.map(|component| component * 0.5),
+                normal,
+                uv: [u, v],
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity((rings * segments * 6) as usize);
+    let row = segments + 1;
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let top_left = ring * row + segment;
+            let bottom_left = top_left + row;
+            // One triangle of each quad collapses at the poles.
+            if ring != rings - 1 {
+                indices.extend([top_left, bottom_left, bottom_left + 1]);
+            }
+            if ring != 0 {
+                indices.extend([top_left, bottom_left + 1, top_left + 1]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// A plane in the XZ plane facing +Y, split into `subdivisions` by
+/// `subdivisions` quads. `subdivisions` is raised to at least 1. The texture
+/// is stretched over the whole plane, with V growing towards +Z.
+pub fn plane(subdivisions: u32) -> (Vec<PrimitiveVertex>, Vec<u32>) {
+    let subdivisions = subdivisions.max(1);
+    let row = subdivisions + 1;
+    let mut vertices = Vec::with_capacity((row * row) as usize);
+    for z in 0..=subdivisions {
+        let v = z as f32 / subdivisions as f32;
+        for x in 0..=subdivisions {
+            let u = x as f32 / subdivisions as f32;
+            vertices.push(PrimitiveVertex {
+                position: [u - 0.5, 0.0, v - 0.5],
+                normal: [0.0, 1.0, 0.0],
+                uv: [u, v],
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity((subdivisions * subdivisions * 6) as usize);
+    for z in 0..subdivisions {
+        for x in 0..subdivisions {
+            let corner = z * row + x;
+            indices.extend([corner, corner + row, corner + row + 1]);
+            indices.extend([corner, corner + row + 1, corner + 1]);
+        }
+    }
+    (vertices, indices)
+}
+
+/// Appends a unit square facing `normal`, pushed `distance` out along it.
+/// `right` x `up` has to equal `normal` for the winding to face outwards.
+fn face(
+    vertices: &mut Vec<PrimitiveVertex>,
+    indices: &mut Vec<u32>,
+    normal: [f32; 3],
+    right: [f32; 3],
+    up: [f32; 3],
+    distance: f32,
+) {
+    let first = vertices.len() as u32;
+    for ([x, y], uv) in FACE_CORNERS {
+        vertices.push(PrimitiveVertex {
+            position: [0, 1, 2]
+                .map(|axis| normal[axis] * distance + right[axis] * x + up[axis] * y),
+            normal,
+            uv,
+        });
+    }
+    indices.extend([first, first + 1, first + 2, first + 2, first + 3, first]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+        [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+    }
+
+    fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+        [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ]
+    }
+
+    fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+
+    /// Checks that every index is in range and every triangle winds
+    /// counter-clockwise seen from the side its vertex normals point to.
+    fn assert_counter_clockwise_from_outside(vertices: &[PrimitiveVertex], indices: &[u32]) {
+        assert_eq!(indices.len() % 3, 0);
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| {
+                let index = triangle[corner] as usize;
+                assert!(index < vertices.len(), "Index {index} out of range");
+                vertices[index]
+            });
+            let face_normal = cross(sub(b.position, a.position), sub(c.position, a.position));
+            let outward = [0, 1, 2].map(|axis| a.normal[axis] + b.normal[axis] + c.normal[axis]);
+            assert!(
+                dot(face_normal, outward) > 0.0,
+                "Triangle {triangle:?} winds clockwise from outside"
+            );
+        }
+    }
+
+    #[test]
+    fn quad() {
+        let (vertices, indices) = super::quad();
+        assert_eq!((vertices.len(), indices.len()), (4, 6));
+        assert_counter_clockwise_from_outside(&vertices, &indices);
+    }
+
+    #[test]
+    fn cube() {
+        let (vertices, indices) = super::cube();
+        assert_eq!((vertices.len(), indices.len()), (24, 36));
+        assert_counter_clockwise_from_outside(&vertices, &indices);
+    }
+
+    #[test]
+    fn uv_sphere() {
+        // 8 segments make 4 rings, where the pole rings have one triangle per
+        // segment and the others two.
+        let (vertices, indices) = super::uv_sphere(8);
+        assert_eq!(
+            (vertices.len(), indices.len()),
+            (5 * 9, (2 * 8 + 2 * 16) * 3)
+        );
+        assert_counter_clockwise_from_outside(&vertices, &indices);
+
+        let (vertices, indices) = super::uv_sphere(0);
+        assert_eq!((vertices.len(), indices.len()), (3 * 4, 2 * 3 * 3));
+        assert_counter_clockwise_from_outside(&vertices, &indices);
+    }
+
+    #[test]
+    fn plane() {
+        let (vertices, indices) = super::plane(3);
+        assert_eq!((vertices.len(), indices.len()), (16, 3 * 3 * 6));
+        assert_counter_clockwise_from_outside(&vertices, &indices);
+
+        let (vertices, indices) = super::plane(0);
+        assert_eq!((vertices.len(), indices.len()), (4, 6));
+        assert_counter_clockwise_from_outside(&vertices, &indices);
+    }
+}
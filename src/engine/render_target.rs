@@ -0,0 +1,273 @@
+use std::sync::Arc;
+
+use ash::{
+    vk::{
+        AccessFlags, AttachmentDescription, AttachmentLoadOp, AttachmentReference,
+        AttachmentStoreOp, ClearColorValue, ClearDepthStencilValue, ClearValue, CommandBuffer,
+        DescriptorImageInfo, Extent2D, Format, Framebuffer, FramebufferCreateInfo,
+        ImageAspectFlags, ImageLayout, ImageUsageFlags, ImageView, Offset2D, PipelineBindPoint,
+        PipelineStageFlags, Rect2D, RenderPass, RenderPassBeginInfo, RenderPassCreateInfo,
+        SampleCountFlags, Sampler, SubpassContents, SubpassDependency, SubpassDescription,
+        Viewport, SUBPASS_EXTERNAL,
+    },
+    Device,
+};
+
+use super::{device_context::DeviceContext, resource::Image};
+
+/// An offscreen color target, with an optional depth buffer, that can be
+/// rendered into and then sampled. The color image is left in
+/// `SHADER_READ_ONLY_OPTIMAL` at the end of the render pass.
+pub struct RenderTarget {
+    context: Arc<DeviceContext>,
+    extent: Extent2D,
+    color: Image,
+    depth: Option<Image>,
+    render_pass: RenderPass,
+    framebuffer: Framebuffer,
+}
+
+impl RenderTarget {
+    pub fn new(
+        context: &Arc<DeviceContext>,
+        extent: Extent2D,
+        color_format: Format,
+        depth_format: Option<Format>,
+    ) -> Self {
+        let device = &context.device;
+        let color = Image::new(
+            context,
+            extent,
+            color_format,
+            ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::SAMPLED,
+            ImageAspectFlags::COLOR,
+        );
+        let depth = depth_format.map(|depth_format| {
+            Image::new(
+                context,
+                extent,
+                depth_format,
+                ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                depth_aspect_mask(depth_format),
+            )
+        });
+        let render_pass = create_render_pass(device, color_format, depth_format);
+
+        let mut attachments = vec![color.view()];
+        attachments.extend(depth.as_ref().map(Image::view));
+        let framebuffer = unsafe {
+            device
+                .create_framebuffer(
+                    &FramebufferCreateInfo::default()
+                        .render_pass(render_pass)
+                        .attachments(&attachments)
+                        .width(extent.width)
+                        .height(extent.height)
+                        .layers(1),
+                    None,
+                )
+                .expect("Failed to create render target framebuffer")
+        };
+
+        Self {
+            context: Arc::clone(context),
+            extent,
+            color,
+            depth,
+            render_pass,
+            framebuffer,
+        }
+    }
+
+    pub fn extent(&self) -> Extent2D {
+        self.extent
+    }
+
+    pub fn render_pass(&self) -> RenderPass {
+        self.render_pass
+    }
+
+    pub fn color_view(&self) -> ImageView {
+        self.color.view()
+    }
+
+    /// Descriptor info for sampling the color image after `end`.
+    pub fn sampled_image_info(&self, sampler: Sampler) -> DescriptorImageInfo {
+        DescriptorImageInfo::default()
+            .sampler(sampler)
+            .image_view(self.color.view())
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+    }
+
+    /// Begins the render pass, clearing the targets, and sets a viewport and
+    /// scissor covering the whole target.
+    pub fn begin(&self, command_buffer: CommandBuffer, clear_color: [f32; 4]) {
+        let mut clear_values = vec![ClearValue {
+            color: ClearColorValue {
+                float32: clear_color,
+            },
+        }];
+        if self.depth.is_some() {
+            clear_values.push(ClearValue {
+                depth_stencil: ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            });
+        }
+        let render_area = Rect2D {
+            offset: Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+
+        let device = &self.context.device;
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &RenderPassBeginInfo::default()
+                    .render_pass(self.render_pass)
+                    .framebuffer(self.framebuffer)
+                    .render_area(render_area)
+                    .clear_values(&clear_values),
+                SubpassContents::INLINE,
+            );
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: self.extent.width as f32,
+                    height: self.extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+        }
+    }
+
+    pub fn end(&self, command_buffer: CommandBuffer) {
+        unsafe {
+            self.context.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+}
+
+/// The GPU must no longer be using the target when it is dropped. The images
+/// are released after the framebuffer referencing them.
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_framebuffer(self.framebuffer, None);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+/// Views of a depth attachment must include the stencil aspect when the
+/// format has one.
+fn depth_aspect_mask(depth_format: Format) -> ImageAspectFlags {
+    match depth_format {
+        Format::D16_UNORM_S8_UINT | Format::D24_UNORM_S8_UINT | Format::D32_SFLOAT_S8_UINT => {
+            ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
+        }
+        Format::S8_UINT => ImageAspectFlags::STENCIL,
+        _ => ImageAspectFlags::DEPTH,
+    }
+}
+
+fn create_render_pass(
+    device: &Device,
+    color_format: Format,
+    depth_format: Option<Format>,
+) -> RenderPass {
+    let mut attachments = vec![AttachmentDescription::default()
+        .format(color_format)
+        .samples(SampleCountFlags::TYPE_1)
+        .load_op(AttachmentLoadOp::CLEAR)
+        .store_op(AttachmentStoreOp::STORE)
+        .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+        .initial_layout(ImageLayout::UNDEFINED)
+        .final_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+    if let Some(depth_format) = depth_format {
+        let stencil_load_op = if depth_aspect_mask(depth_format).contains(ImageAspectFlags::STENCIL)
+        {
+            AttachmentLoadOp::CLEAR
+        } else {
+            AttachmentLoadOp::DONT_CARE
+        };
+        attachments.push(
+            AttachmentDescription::default()
+                .format(depth_format)
+                .samples(SampleCountFlags::TYPE_1)
+                .load_op(AttachmentLoadOp::CLEAR)
+                .store_op(AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(stencil_load_op)
+                .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+                .initial_layout(ImageLayout::UNDEFINED)
+                .final_layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        );
+    }
+
+    let color_attachment_refs = [AttachmentReference::default()
+        .attachment(0)
+        .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+    let depth_attachment_ref = AttachmentReference::default()
+        .attachment(1)
+        .layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+    let mut subpass = SubpassDescription::default()
+        .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs);
+    if depth_format.is_some() {
+        subpass = subpass.depth_stencil_attachment(&depth_attachment_ref);
+    }
+    let subpasses = [subpass];
+
+    // Wait for earlier reads of the color image, and the previous use's depth
+    // writes, before overwriting them, and make the color writes visible to
+    // fragment shaders sampling it afterwards.
+    let (mut src_stage_mask, mut src_access_mask) = (
+        PipelineStageFlags::FRAGMENT_SHADER,
+        AccessFlags::SHADER_READ,
+    );
+    if depth_format.is_some() {
+        src_stage_mask |= PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        src_access_mask |= AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+    }
+    let dependencies = [
+        SubpassDependency::default()
+            .src_subpass(SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(src_stage_mask)
+            .src_access_mask(src_access_mask)
+            .dst_stage_mask(
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                AccessFlags::COLOR_ATTACHMENT_WRITE | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+        SubpassDependency::default()
+            .src_subpass(0)
+            .dst_subpass(SUBPASS_EXTERNAL)
+            .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(AccessFlags::SHADER_READ),
+    ];
+
+    unsafe {
+        device
+            .create_render_pass(
+                &RenderPassCreateInfo::default()
+                    .attachments(&attachments)
+                    .subpasses(&subpasses)
+                    .dependencies(&dependencies),
+                None,
+            )
+            .expect("Failed to create render target render pass")
+    }
+}
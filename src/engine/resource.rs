@@ -0,0 +1,650 @@
+use std::{ffi::c_void, ptr, sync::Arc};
+
+use ash::vk::{
+    self, BorderColor, BufferCreateInfo, BufferUsageFlags, DeviceMemory, DeviceSize, Extent2D,
+    Extent3D, Format, ImageAspectFlags, ImageCreateInfo, ImageLayout, ImageSubresourceRange,
+    ImageTiling, ImageType, ImageUsageFlags, ImageView, ImageViewCreateInfo, ImageViewType,
+    MemoryAllocateInfo, MemoryMapFlags, MemoryPropertyFlags, MemoryRequirements, SampleCountFlags,
+    SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode, ShaderModule, ShaderModuleCreateInfo,
+    SharingMode,
+};
+
+use super::{device_context::DeviceContext, memory::find_memory_type_index};
+
+/// A buffer together with the memory bound to it. Both are released on drop.
+pub struct Buffer {
+    context: Arc<DeviceContext>,
+    buffer: vk::Buffer,
+    memory: DeviceMemory,
+    size: DeviceSize,
+}
+
+impl Buffer {
+    pub fn new(
+        context: &Arc<DeviceContext>,
+        size: DeviceSize,
+        usage: BufferUsageFlags,
+        memory_flags: MemoryPropertyFlags,
+    ) -> Self {
+        let device = &context.device;
+        unsafe {
+            let buffer = device
+                .create_buffer(
+                    &BufferCreateInfo::default()
+                        .size(size)
+                        .usage(usage)
+                        .sharing_mode(SharingMode::EXCLUSIVE),
+                    None,
+                )
+                .expect("Failed to create buffer");
+            let memory = allocate_memory(
+                context,
+                device.get_buffer_memory_requirements(buffer),
+                memory_flags,
+            );
+            device
+                .bind_buffer_memory(buffer, memory, 0)
+                .expect("Failed to bind buffer memory");
+
+            Self {
+                context: Arc::clone(context),
+                buffer,
+                memory,
+                size,
+            }
+        }
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn memory(&self) -> DeviceMemory {
+        self.memory
+    }
+
+    pub fn size(&self) -> DeviceSize {
+        self.size
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device.destroy_buffer(self.buffer, None);
+            self.context.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// A `HOST_VISIBLE | HOST_COHERENT` buffer that stays mapped for its whole
+/// lifetime, so updates are a plain memory copy with no map/unmap per frame.
+/// Meant for uniform data rewritten every frame.
+pub struct MappedBuffer {
+    buffer: Buffer,
+    mapped: *mut c_void,
+}
+
+impl MappedBuffer {
+    pub fn new(context: &Arc<DeviceContext>, size: DeviceSize, usage: BufferUsageFlags) -> Self {
+        let buffer = Buffer::new(
+            context,
+            size,
+            usage,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        );
+        let mapped = unsafe {
+            context
+                .device
+                .map_memory(buffer.memory(), 0, vk::WHOLE_SIZE, MemoryMapFlags::empty())
+                .expect("Failed to map buffer memory")
+        };
+        Self { buffer, mapped }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// Copies `data` to `offset`. The GPU must not be reading that range, so
+    /// each frame in flight needs its own buffer or region.
+    pub fn write(&mut self, offset: DeviceSize, data: &[u8]) {
+        assert!(
+            offset + data.len() as DeviceSize <= self.buffer.size(),
+            "Write of {} bytes at offset {offset} exceeds the buffer size of {}",
+            data.len(),
+            self.buffer.size()
+        );
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.mapped.cast::<u8>().add(offset as usize),
+                data.len(),
+            );
+        }
+    }
+}
+
+/// Unmaps before the inner `Buffer` frees the memory.
+impl Drop for MappedBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.buffer
+                .context
+                .device
+                .unmap_memory(self.buffer.memory());
+        }
+    }
+}
+
+/// An array of equally sized uniform blocks in one buffer, one per object,
+/// selected per draw with a dynamic offset into a `UNIFORM_BUFFER_DYNAMIC`
+/// descriptor instead of a descriptor set per object. Drawn with
+/// `BaseConfig::draw_with_uniforms`.
+pub struct DynamicUniformBuffer {
+    buffer: MappedBuffer,
+    /// Element size rounded up to `minUniformBufferOffsetAlignment`.
+    stride: DeviceSize,
+    len: usize,
+}
+
+impl DynamicUniformBuffer {
+    pub fn new(context: &Arc<DeviceContext>, element_size: DeviceSize, len: usize) -> Self {
+        let alignment = unsafe {
+            context
+                .instance
+                .get_physical_device_properties(context.physical_device)
+                .limits
+                .min_uniform_buffer_offset_alignment
+        };
+        let stride = element_size.next_multiple_of(alignment.max(1));
+        Self {
+            buffer: MappedBuffer::new(
+                context,
+                stride * len as DeviceSize,
+                BufferUsageFlags::UNIFORM_BUFFER,
+            ),
+            stride,
+            len,
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        self.buffer.buffer()
+    }
+
+    /// Distance between consecutive elements, which is also the range the
+    /// descriptor should cover.
+    pub fn stride(&self) -> DeviceSize {
+        self.stride
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The dynamic offset selecting element `index` when binding.
+    pub fn dynamic_offset(&self, index: usize) -> u32 {
+        assert!(index < self.len, "Element {index} out of {}", self.len);
+        (index as DeviceSize * self.stride) as u32
+    }
+
+    /// Copies `data` into element `index`. The same rules as for
+    /// `MappedBuffer::write` apply.
+    pub fn write(&mut self, index: usize, data: &[u8]) {
+        assert!(
+            data.len() as DeviceSize <= self.stride,
+            "Element data of {} bytes exceeds the stride of {}",
+            data.len(),
+            self.stride
+        );
+        let offset = self.dynamic_offset(index) as DeviceSize;
+        self.buffer.write(offset, data);
+    }
+}
+
+/// One `MappedBuffer` per frame in flight, for data rewritten every frame
+/// such as dynamic vertex data. Each frame writes its own copy through
+/// `BaseConfig::update_buffer_deferred`, so the GPU never reads a copy the
+/// CPU is overwriting.
+pub struct PerFrameBuffer {
+    buffers: Vec<MappedBuffer>,
+    usage: BufferUsageFlags,
+    /// Slot written last, i.e. the one the current frame reads.
+    current: usize,
+}
+
+impl PerFrameBuffer {
+    /// Panics if `size` is 0, as Vulkan has no zero sized buffers.
+    pub fn new(
+        context: &Arc<DeviceContext>,
+        size: DeviceSize,
+        usage: BufferUsageFlags,
+        frames_in_flight: usize,
+    ) -> Self {
+        assert!(size > 0, "Per frame buffers need a size above 0 bytes");
+        Self {
+            buffers: (0..frames_in_flight)
+                .map(|_| MappedBuffer::new(context, size, usage))
+                .collect(),
+            usage,
+            current: 0,
+        }
+    }
+
+    /// The copy written for the current frame, to bind in its draws.
+    pub fn buffer(&self) -> &Buffer {
+        self.buffers[self.current].buffer()
+    }
+
+    /// Writes `data` to the copy of frame slot `slot` and makes it current.
+    /// Grows that copy when `data` does not fit, returning the old one,
+    /// which frames in flight may still read.
+    pub(super) fn write(
+        &mut self,
+        context: &Arc<DeviceContext>,
+        slot: usize,
+        data: &[u8],
+    ) -> Option<MappedBuffer> {
+        let size = data.len() as DeviceSize;
+        let replaced = (size > self.buffers[slot].buffer().size()).then(|| {
+            std::mem::replace(
+                &mut self.buffers[slot],
+                MappedBuffer::new(context, size, self.usage),
+            )
+        });
+        self.buffers[slot].write(0, data);
+        self.current = slot;
+        replaced
+    }
+}
+
+/// A single-sample 2D image or 2D array in device local memory with a view
+/// covering all of it. The view, image and memory are released on drop.
+pub struct Image {
+    context: Arc<DeviceContext>,
+    image: vk::Image,
+    memory: DeviceMemory,
+    view: ImageView,
+    array_layers: u32,
+    mip_levels: u32,
+}
+
+impl Image {
+    pub fn new(
+        context: &Arc<DeviceContext>,
+        extent: Extent2D,
+        format: Format,
+        usage: ImageUsageFlags,
+        aspect_mask: ImageAspectFlags,
+    ) -> Self {
+        Self::create(context, extent, format, usage, aspect_mask, None, 1)
+    }
+
+    /// A color image of `array_layers` layers and `mip_levels` mip levels
+    /// viewed as `TYPE_2D_ARRAY`, which shaders sample as `sampler2DArray`.
+    /// `array_layers` must not exceed `max_image_array_layers` and
+    /// `mip_levels` must not exceed `mip_level_count(extent)`.
+    pub fn new_array(
+        context: &Arc<DeviceContext>,
+        extent: Extent2D,
+        format: Format,
+        usage: ImageUsageFlags,
+        array_layers: u32,
+        mip_levels: u32,
+    ) -> Self {
+        Self::create(
+            context,
+            extent,
+            format,
+            usage,
+            ImageAspectFlags::COLOR,
+            Some(array_layers),
+            mip_levels,
+        )
+    }
+
+    /// `array_layers` of `None` is a single layer viewed as `TYPE_2D`.
+    fn create(
+        context: &Arc<DeviceContext>,
+        extent: Extent2D,
+        format: Format,
+        usage: ImageUsageFlags,
+        aspect_mask: ImageAspectFlags,
+        array_layers: Option<u32>,
+        mip_levels: u32,
+    ) -> Self {
+        let (array_layers, view_type) = match array_layers {
+            Some(layers) => (layers, ImageViewType::TYPE_2D_ARRAY),
+            None => (1, ImageViewType::TYPE_2D),
+        };
+        let device = &context.device;
+        unsafe {
+            let image = device
+                .create_image(
+                    &ImageCreateInfo::default()
+                        .image_type(ImageType::TYPE_2D)
+                        .format(format)
+                        .extent(Extent3D {
+                            width: extent.width,
+                            height: extent.height,
+                            depth: 1,
+                        })
+                        .mip_levels(mip_levels)
+                        .array_layers(array_layers)
+                        .samples(SampleCountFlags::TYPE_1)
+                        .tiling(ImageTiling::OPTIMAL)
+                        .usage(usage)
+                        .sharing_mode(SharingMode::EXCLUSIVE)
+                        .initial_layout(ImageLayout::UNDEFINED),
+                    None,
+                )
+                .expect("Failed to create image");
+            let memory = allocate_memory(
+                context,
+                device.get_image_memory_requirements(image),
+                MemoryPropertyFlags::DEVICE_LOCAL,
+            );
+            device
+                .bind_image_memory(image, memory, 0)
+                .expect("Failed to bind image memory");
+
+            let view = device
+                .create_image_view(
+                    &ImageViewCreateInfo::default()
+                        .image(image)
+                        .view_type(view_type)
+                        .format(format)
+                        .subresource_range(
+                            ImageSubresourceRange::default()
+                                .aspect_mask(aspect_mask)
+                                .level_count(mip_levels)
+                                .layer_count(array_layers),
+                        ),
+                    None,
+                )
+                .expect("Failed to create image view");
+
+            Self {
+                context: Arc::clone(context),
+                image,
+                memory,
+                view,
+                array_layers,
+                mip_levels,
+            }
+        }
+    }
+
+    pub fn handle(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn view(&self) -> ImageView {
+        self.view
+    }
+
+    pub fn array_layers(&self) -> u32 {
+        self.array_layers
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+}
+
+/// The length of a full mip chain for `extent`, down to 1x1:
+/// `floor(log2(max(width, height))) + 1`.
+pub fn mip_level_count(extent: Extent2D) -> u32 {
+    u32::BITS - extent.width.max(extent.height).max(1).leading_zeros()
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// How a sampler filters and wraps. `border_color` is only read with
+/// `SamplerAddressMode::CLAMP_TO_BORDER`, which requires one.
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerDesc {
+    pub filter: vk::Filter,
+    pub address_mode: SamplerAddressMode,
+    pub border_color: Option<BorderColor>,
+    /// Maximum anisotropy, sharpening textures seen at grazing angles, or
+    /// `None` for isotropic filtering. Clamped to `maxSamplerAnisotropy`, so
+    /// the default `f32::MAX` picks the device maximum. Ignored without the
+    /// `sampler_anisotropy` device feature.
+    pub anisotropy: Option<f32>,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            filter: vk::Filter::LINEAR,
+            address_mode: SamplerAddressMode::REPEAT,
+            border_color: None,
+            anisotropy: Some(f32::MAX),
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// Tiles the texture, e.g. across a floor.
+    pub fn repeat() -> Self {
+        Self::default()
+    }
+
+    /// Stretches the edge texels, so UI textures do not bleed into each
+    /// other.
+    pub fn clamp_to_edge() -> Self {
+        Self {
+            address_mode: SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Self::default()
+        }
+    }
+
+    /// Returns `border_color` outside the texture.
+    pub fn clamp_to_border(border_color: BorderColor) -> Self {
+        Self {
+            address_mode: SamplerAddressMode::CLAMP_TO_BORDER,
+            border_color: Some(border_color),
+            ..Self::default()
+        }
+    }
+}
+
+/// Owns a sampler and destroys it on drop.
+pub struct SamplerGuard {
+    context: Arc<DeviceContext>,
+    sampler: vk::Sampler,
+}
+
+impl SamplerGuard {
+    pub fn new(context: &Arc<DeviceContext>, desc: &SamplerDesc) -> Self {
+        let anisotropy = desc.anisotropy.and_then(|anisotropy| unsafe {
+            let instance = &context.instance;
+            let supported = instance
+                .get_physical_device_features(context.physical_device)
+                .sampler_anisotropy
+                == vk::TRUE;
+            let max_anisotropy = instance
+                .get_physical_device_properties(context.physical_device)
+                .limits
+                .max_sampler_anisotropy;
+            supported_anisotropy(anisotropy, supported, max_anisotropy)
+        });
+        let sampler = unsafe {
+            context
+                .device
+                .create_sampler(
+                    &SamplerCreateInfo::default()
+                        .anisotropy_enable(anisotropy.is_some())
+                        .max_anisotropy(anisotropy.unwrap_or(1.0))
+                        .mag_filter(desc.filter)
+                        .min_filter(desc.filter)
+                        .mipmap_mode(SamplerMipmapMode::LINEAR)
+                        .address_mode_u(desc.address_mode)
+                        .address_mode_v(desc.address_mode)
+                        .address_mode_w(desc.address_mode)
+                        .border_color(
+                            desc.border_color
+                                .unwrap_or(BorderColor::FLOAT_TRANSPARENT_BLACK),
+                        )
+                        .max_lod(vk::LOD_CLAMP_NONE),
+                    None,
+                )
+                .expect("Failed to create sampler")
+        };
+        Self {
+            context: Arc::clone(context),
+            sampler,
+        }
+    }
+
+    pub fn handle(&self) -> vk::Sampler {
+        self.sampler
+    }
+}
+
+impl Drop for SamplerGuard {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+/// The anisotropy a sampler can use for `requested`: between 1.0 and
+/// `max_anisotropy`, or `None` without device support.
+fn supported_anisotropy(requested: f32, supported: bool, max_anisotropy: f32) -> Option<f32> {
+    supported.then(|| requested.clamp(1.0, max_anisotropy))
+}
+
+/// A shader module that only needs to live until the pipelines using it are
+/// created.
+pub struct ShaderModuleGuard {
+    context: Arc<DeviceContext>,
+    module: ShaderModule,
+}
+
+impl ShaderModuleGuard {
+    pub fn new(context: &Arc<DeviceContext>, code: &[u32]) -> Self {
+        let module = unsafe {
+            context
+                .device
+                .create_shader_module(&ShaderModuleCreateInfo::default().code(code), None)
+                .expect("Failed to create shader module")
+        };
+        Self {
+            context: Arc::clone(context),
+            module,
+        }
+    }
+
+    pub fn handle(&self) -> ShaderModule {
+        self.module
+    }
+}
+
+impl Drop for ShaderModuleGuard {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device.destroy_shader_module(self.module, None);
+        }
+    }
+}
+
+/// Owns a pipeline and destroys it on drop. The GPU must no longer be using
+/// it by then.
+pub struct PipelineGuard {
+    context: Arc<DeviceContext>,
+    pipeline: vk::Pipeline,
+}
+
+impl PipelineGuard {
+    pub fn new(context: &Arc<DeviceContext>, pipeline: vk::Pipeline) -> Self {
+        Self {
+            context: Arc::clone(context),
+            pipeline,
+        }
+    }
+
+    pub fn handle(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+}
+
+impl Drop for PipelineGuard {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+        }
+    }
+}
+
+fn allocate_memory(
+    context: &DeviceContext,
+    memory_requirements: MemoryRequirements,
+    memory_flags: MemoryPropertyFlags,
+) -> DeviceMemory {
+    unsafe {
+        let memory_properties = context
+            .instance
+            .get_physical_device_memory_properties(context.physical_device);
+        let memory_type_index = find_memory_type_index(
+            &memory_properties,
+            memory_requirements.memory_type_bits,
+            memory_flags,
+        )
+        .expect("No suitable memory type");
+        context
+            .device
+            .allocate_memory(
+                &MemoryAllocateInfo::default()
+                    .allocation_size(memory_requirements.size)
+                    .memory_type_index(memory_type_index),
+                None,
+            )
+            .expect("Failed to allocate memory")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anisotropy_is_clamped_to_the_device_limit() {
+        assert_eq!(supported_anisotropy(16.0, true, 8.0), Some(8.0));
+        assert_eq!(supported_anisotropy(4.0, true, 16.0), Some(4.0));
+        assert_eq!(supported_anisotropy(0.5, true, 16.0), Some(1.0));
+        assert_eq!(supported_anisotropy(f32::MAX, true, 16.0), Some(16.0));
+    }
+
+    #[test]
+    fn anisotropy_needs_the_feature() {
+        assert_eq!(supported_anisotropy(16.0, false, 16.0), None);
+    }
+
+    #[test]
+    fn mip_chain_goes_down_to_one_pixel() {
+        let extent = |width, height| Extent2D { width, height };
+        assert_eq!(mip_level_count(extent(1, 1)), 1);
+        assert_eq!(mip_level_count(extent(2, 1)), 2);
+        assert_eq!(mip_level_count(extent(256, 256)), 9);
+        assert_eq!(mip_level_count(extent(255, 64)), 8);
+        assert_eq!(mip_level_count(extent(640, 480)), 10);
+        assert_eq!(mip_level_count(extent(0, 0)), 1);
+    }
+}
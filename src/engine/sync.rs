@@ -0,0 +1,441 @@
+use ash::{
+    vk::{
+        self, AccessFlags, CommandBuffer, DependencyFlags, ImageAspectFlags, ImageLayout,
+        ImageMemoryBarrier, ImageSubresourceRange, PipelineStageFlags, QUEUE_FAMILY_IGNORED,
+        REMAINING_ARRAY_LAYERS, REMAINING_MIP_LEVELS,
+    },
+    Device,
+};
+
+/// A layout transition of one image together with the stages it has to wait
+/// for and block. Start from one of the presets, narrow the subresource range
+/// if needed, then `record` it.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageBarrier {
+    src_stage_mask: PipelineStageFlags,
+    dst_stage_mask: PipelineStageFlags,
+    barrier: ImageMemoryBarrier<'static>,
+}
+
+impl ImageBarrier {
+    /// A transition of every mip level and layer of `image` between the given
+    /// states, for cases no preset covers.
+    pub fn new(
+        image: vk::Image,
+        aspect_mask: ImageAspectFlags,
+        (src_stage_mask, src_access_mask, old_layout): (
+            PipelineStageFlags,
+            AccessFlags,
+            ImageLayout,
+        ),
+        (dst_stage_mask, dst_access_mask, new_layout): (
+            PipelineStageFlags,
+            AccessFlags,
+            ImageLayout,
+        ),
+    ) -> Self {
+        Self {
+            src_stage_mask,
+            dst_stage_mask,
+            barrier: ImageMemoryBarrier::default()
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(
+                    ImageSubresourceRange::default()
+                        .aspect_mask(aspect_mask)
+                        .level_count(REMAINING_MIP_LEVELS)
+                        .layer_count(REMAINING_ARRAY_LAYERS),
+                ),
+        }
+    }
+
+    /// Before the first upload into a fresh image. Its previous contents are
+    /// discarded.
+    pub fn undefined_to_transfer_dst(image: vk::Image, aspect_mask: ImageAspectFlags) -> Self {
+        Self::new(
+            image,
+            aspect_mask,
+            (
+                PipelineStageFlags::TOP_OF_PIPE,
+                AccessFlags::empty(),
+                ImageLayout::UNDEFINED,
+            ),
+            (
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::TRANSFER_WRITE,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+        )
+    }
+
+    /// After an upload, before fragment shaders sample the image.
+    pub fn transfer_dst_to_shader_read(image: vk::Image, aspect_mask: ImageAspectFlags) -> Self {
+        Self::new(
+            image,
+            aspect_mask,
+            (
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::TRANSFER_WRITE,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+            (
+                PipelineStageFlags::FRAGMENT_SHADER,
+                AccessFlags::SHADER_READ,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+        )
+    }
+
+    /// While generating mipmaps, after a level has been written, before it is
+    /// blitted into the next one.
+    pub fn transfer_dst_to_transfer_src(image: vk::Image, aspect_mask: ImageAspectFlags) -> Self {
+        Self::new(
+            image,
+            aspect_mask,
+            (
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::TRANSFER_WRITE,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+            (
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::TRANSFER_READ,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ),
+        )
+    }
+
+    /// After generating mipmaps, before fragment shaders sample the levels
+    /// that were blitted from.
+    pub fn transfer_src_to_shader_read(image: vk::Image, aspect_mask: ImageAspectFlags) -> Self {
+        Self::new(
+            image,
+            aspect_mask,
+            (
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::TRANSFER_READ,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ),
+            (
+                PipelineStageFlags::FRAGMENT_SHADER,
+                AccessFlags::SHADER_READ,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+        )
+    }
+
+    /// After rendering into a color target outside a render pass dependency,
+    /// before fragment shaders sample it.
+    pub fn color_attachment_to_shader_read(image: vk::Image) -> Self {
+        Self::new(
+            image,
+            ImageAspectFlags::COLOR,
+            (
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            (
+                PipelineStageFlags::FRAGMENT_SHADER,
+                AccessFlags::SHADER_READ,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+        )
+    }
+
+    /// After sampling, before rendering into the image again.
+    pub fn shader_read_to_color_attachment(image: vk::Image) -> Self {
+        Self::new(
+            image,
+            ImageAspectFlags::COLOR,
+            (
+                PipelineStageFlags::FRAGMENT_SHADER,
+                AccessFlags::SHADER_READ,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            (
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+        )
+    }
+
+    /// Limits the barrier to `level_count` mip levels from `base_mip_level`,
+    /// e.g. one level at a time while generating mipmaps.
+    pub fn mip_levels(mut self, base_mip_level: u32, level_count: u32) -> Self {
+        self.barrier.subresource_range.base_mip_level = base_mip_level;
+        self.barrier.subresource_range.level_count = level_count;
+        self
+    }
+
+    /// Limits the barrier to `layer_count` array layers from `base_array_layer`.
+    pub fn array_layers(mut self, base_array_layer: u32, layer_count: u32) -> Self {
+        self.barrier.subresource_range.base_array_layer = base_array_layer;
+        self.barrier.subresource_range.layer_count = layer_count;
+        self
+    }
+
+    pub fn record(&self, device: &Device, command_buffer: CommandBuffer) {
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                self.src_stage_mask,
+                self.dst_stage_mask,
+                DependencyFlags::empty(),
+                &[],
+                &[],
+                &[self.barrier],
+            );
+        }
+    }
+}
+
+/// An execution and memory dependency over all resources, for buffers and
+/// other cases without a layout to transition. Start from one of the presets
+/// and `record` it.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryBarrier {
+    src_stage_mask: PipelineStageFlags,
+    dst_stage_mask: PipelineStageFlags,
+    barrier: vk::MemoryBarrier<'static>,
+}
+
+impl MemoryBarrier {
+    /// A dependency between the given stages and accesses, for cases no
+    /// preset covers.
+    pub fn new(
+        (src_stage_mask, src_access_mask): (PipelineStageFlags, AccessFlags),
+        (dst_stage_mask, dst_access_mask): (PipelineStageFlags, AccessFlags),
+    ) -> Self {
+        Self {
+            src_stage_mask,
+            dst_stage_mask,
+            barrier: vk::MemoryBarrier::default()
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask),
+        }
+    }
+
+    /// After any earlier work that may have written a resource, before a
+    /// transfer reads it, e.g. to copy it out for readback.
+    pub fn all_writes_to_transfer_read() -> Self {
+        Self::new(
+            (PipelineStageFlags::ALL_COMMANDS, AccessFlags::MEMORY_WRITE),
+            (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_READ),
+        )
+    }
+
+    /// After a copy into host visible memory, before the host reads it.
+    pub fn transfer_write_to_host_read() -> Self {
+        Self::new(
+            (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+            (PipelineStageFlags::HOST, AccessFlags::HOST_READ),
+        )
+    }
+
+    /// After uploading vertex or index data, before draws read it.
+    pub fn transfer_write_to_vertex_input() -> Self {
+        Self::new(
+            (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+            (
+                PipelineStageFlags::VERTEX_INPUT,
+                AccessFlags::VERTEX_ATTRIBUTE_READ | AccessFlags::INDEX_READ,
+            ),
+        )
+    }
+
+    pub fn record(&self, device: &Device, command_buffer: CommandBuffer) {
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                self.src_stage_mask,
+                self.dst_stage_mask,
+                DependencyFlags::empty(),
+                &[self.barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source and destination stage, access and layout of `barrier`.
+    fn image_states(barrier: ImageBarrier) -> [(PipelineStageFlags, AccessFlags, ImageLayout); 2] {
+        [
+            (
+                barrier.src_stage_mask,
+                barrier.barrier.src_access_mask,
+                barrier.barrier.old_layout,
+            ),
+            (
+                barrier.dst_stage_mask,
+                barrier.barrier.dst_access_mask,
+                barrier.barrier.new_layout,
+            ),
+        ]
+    }
+
+    fn memory_states(barrier: MemoryBarrier) -> [(PipelineStageFlags, AccessFlags); 2] {
+        [
+            (barrier.src_stage_mask, barrier.barrier.src_access_mask),
+            (barrier.dst_stage_mask, barrier.barrier.dst_access_mask),
+        ]
+    }
+
+    #[test]
+    fn image_presets() {
+        let image = vk::Image::null();
+        assert_eq!(
+            image_states(ImageBarrier::undefined_to_transfer_dst(
+                image,
+                ImageAspectFlags::COLOR
+            )),
+            [
+                (
+                    PipelineStageFlags::TOP_OF_PIPE,
+                    AccessFlags::empty(),
+                    ImageLayout::UNDEFINED
+                ),
+                (
+                    PipelineStageFlags::TRANSFER,
+                    AccessFlags::TRANSFER_WRITE,
+                    ImageLayout::TRANSFER_DST_OPTIMAL
+                ),
+            ]
+        );
+        assert_eq!(
+            image_states(ImageBarrier::transfer_dst_to_transfer_src(
+                image,
+                ImageAspectFlags::COLOR
+            )),
+            [
+                (
+                    PipelineStageFlags::TRANSFER,
+                    AccessFlags::TRANSFER_WRITE,
+                    ImageLayout::TRANSFER_DST_OPTIMAL
+                ),
+                (
+                    PipelineStageFlags::TRANSFER,
+                    AccessFlags::TRANSFER_READ,
+                    ImageLayout::TRANSFER_SRC_OPTIMAL
+                ),
+            ]
+        );
+        assert_eq!(
+            image_states(ImageBarrier::transfer_src_to_shader_read(
+                image,
+                ImageAspectFlags::COLOR
+            )),
+            [
+                (
+                    PipelineStageFlags::TRANSFER,
+                    AccessFlags::TRANSFER_READ,
+                    ImageLayout::TRANSFER_SRC_OPTIMAL
+                ),
+                (
+                    PipelineStageFlags::FRAGMENT_SHADER,
+                    AccessFlags::SHADER_READ,
+                    ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                ),
+            ]
+        );
+        assert_eq!(
+            image_states(ImageBarrier::transfer_dst_to_shader_read(
+                image,
+                ImageAspectFlags::COLOR
+            )),
+            [
+                (
+                    PipelineStageFlags::TRANSFER,
+                    AccessFlags::TRANSFER_WRITE,
+                    ImageLayout::TRANSFER_DST_OPTIMAL
+                ),
+                (
+                    PipelineStageFlags::FRAGMENT_SHADER,
+                    AccessFlags::SHADER_READ,
+                    ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                ),
+            ]
+        );
+        let color_attachment = (
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+        let shader_read = (
+            PipelineStageFlags::FRAGMENT_SHADER,
+            AccessFlags::SHADER_READ,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        assert_eq!(
+            image_states(ImageBarrier::color_attachment_to_shader_read(image)),
+            [color_attachment, shader_read]
+        );
+        assert_eq!(
+            image_states(ImageBarrier::shader_read_to_color_attachment(image)),
+            [shader_read, color_attachment]
+        );
+    }
+
+    #[test]
+    fn image_barriers_cover_the_whole_image_until_narrowed() {
+        let barrier =
+            ImageBarrier::undefined_to_transfer_dst(vk::Image::null(), ImageAspectFlags::DEPTH);
+        let range = barrier.barrier.subresource_range;
+        assert_eq!(range.aspect_mask, ImageAspectFlags::DEPTH);
+        assert_eq!(
+            (range.base_mip_level, range.level_count),
+            (0, REMAINING_MIP_LEVELS)
+        );
+        assert_eq!(
+            (range.base_array_layer, range.layer_count),
+            (0, REMAINING_ARRAY_LAYERS)
+        );
+
+        let range = barrier
+            .mip_levels(2, 1)
+            .array_layers(3, 4)
+            .barrier
+            .subresource_range;
+        assert_eq!((range.base_mip_level, range.level_count), (2, 1));
+        assert_eq!((range.base_array_layer, range.layer_count), (3, 4));
+    }
+
+    #[test]
+    fn memory_presets() {
+        assert_eq!(
+            memory_states(MemoryBarrier::all_writes_to_transfer_read()),
+            [
+                (PipelineStageFlags::ALL_COMMANDS, AccessFlags::MEMORY_WRITE),
+                (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_READ),
+            ]
+        );
+        assert_eq!(
+            memory_states(MemoryBarrier::transfer_write_to_host_read()),
+            [
+                (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+                (PipelineStageFlags::HOST, AccessFlags::HOST_READ),
+            ]
+        );
+        assert_eq!(
+            memory_states(MemoryBarrier::transfer_write_to_vertex_input()),
+            [
+                (PipelineStageFlags::TRANSFER, AccessFlags::TRANSFER_WRITE),
+                (
+                    PipelineStageFlags::VERTEX_INPUT,
+                    AccessFlags::VERTEX_ATTRIBUTE_READ | AccessFlags::INDEX_READ
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use ash::vk::{
+    Format, VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate,
+};
+
+/// A `#[repr(C)]` struct usable as the per-vertex data of one binding.
+/// Implement it with `impl_vertex!` rather than by hand.
+pub trait Vertex: Sized {
+    fn attribute_descriptions(binding: u32) -> Vec<VertexInputAttributeDescription>;
+
+    fn binding_description(binding: u32) -> VertexInputBindingDescription {
+        VertexInputBindingDescription::default()
+            .binding(binding)
+            .stride(size_of::<Self>() as u32)
+            .input_rate(VertexInputRate::VERTEX)
+    }
+}
+
+/// Field types a vertex attribute can have, with the format the shader
+/// reads them as.
+pub trait VertexAttribute {
+    const FORMAT: Format;
+}
+
+impl VertexAttribute for f32 {
+    const FORMAT: Format = Format::R32_SFLOAT;
+}
+
+impl VertexAttribute for [f32; 2] {
+    const FORMAT: Format = Format::R32G32_SFLOAT;
+}
+
+impl VertexAttribute for [f32; 3] {
+    const FORMAT: Format = Format::R32G32B32_SFLOAT;
+}
+
+impl VertexAttribute for [f32; 4] {
+    const FORMAT: Format = Format::R32G32B32A32_SFLOAT;
+}
+
+impl VertexAttribute for u32 {
+    const FORMAT: Format = Format::R32_UINT;
+}
+
+impl VertexAttribute for [u32; 2] {
+    const FORMAT: Format = Format::R32G32_UINT;
+}
+
+impl VertexAttribute for [u32; 3] {
+    const FORMAT: Format = Format::R32G32B32_UINT;
+}
+
+impl VertexAttribute for [u32; 4] {
+    const FORMAT: Format = Format::R32G32B32A32_UINT;
+}
+
+impl VertexAttribute for i32 {
+    const FORMAT: Format = Format::R32_SINT;
+}
+
+/// Normalized to `0.0..=1.0`, e.g. for packed vertex colors.
+impl VertexAttribute for [u8; 4] {
+    const FORMAT: Format = Format::R8G8B8A8_UNORM;
+}
+
+/// The format of the field `field` selects. Only used by `impl_vertex!`, to
+/// name a field's type without spelling it out.
+#[doc(hidden)]
+pub fn field_format<V, T: VertexAttribute>(_field: fn(&V) -> &T) -> Format {
+    T::FORMAT
+}
+
+/// Implements `Vertex` for a `#[repr(C)]` struct. The listed fields become
+/// attributes at consecutive shader locations starting at 0, with offsets
+/// from `offset_of!` and formats from their types:
+///
+/// ```no_run
+/// use malbi::impl_vertex;
+///
+/// #[repr(C)]
+/// struct MeshVertex {
+///     position: [f32; 3],
+///     normal: [f32; 3],
+///     uv: [f32; 2],
+/// }
+/// impl_vertex!(MeshVertex { position, normal, uv });
+/// ```
+#[macro_export]
+macro_rules! impl_vertex {
+    ($vertex:ty { $($field:ident),+ $(,)? }) => {
+        impl $crate::engine::vertex::Vertex for $vertex {
+            fn attribute_descriptions(
+                binding: u32,
+            ) -> Vec<::ash::vk::VertexInputAttributeDescription> {
+                let formats_and_offsets = [$((
+                    $crate::engine::vertex::field_format(|vertex: &$vertex| &vertex.$field),
+                    ::std::mem::offset_of!($vertex, $field) as u32,
+                )),+];
+                formats_and_offsets
+                    .into_iter()
+                    .enumerate()
+                    .map(|(location, (format, offset))| {
+                        ::ash::vk::VertexInputAttributeDescription::default()
+                            .location(location as u32)
+                            .binding(binding)
+                            .format(format)
+                            .offset(offset)
+                    })
+                    .collect()
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::primitives::PrimitiveVertex;
+
+    #[test]
+    fn primitive_vertex_attributes() {
+        let attributes = PrimitiveVertex::attribute_descriptions(1);
+        let layout: Vec<_> = attributes
+            .iter()
+            .map(|attribute| {
+                assert_eq!(attribute.binding, 1);
+                (attribute.location, attribute.format, attribute.offset)
+            })
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                (0, Format::R32G32B32_SFLOAT, 0),
+                (1, Format::R32G32B32_SFLOAT, 12),
+                (2, Format::R32G32_SFLOAT, 24),
+            ]
+        );
+
+        let binding = PrimitiveVertex::binding_description(1);
+        assert_eq!(binding.binding, 1);
+        assert_eq!(binding.stride, 32);
+        assert_eq!(binding.input_rate, VertexInputRate::VERTEX);
+    }
+}
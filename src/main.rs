@@ -1,13 +1,221 @@
-use engine::app::Application;
-use winit::{dpi::LogicalSize, event_loop::EventLoop};
-mod engine;
+use ash::vk::CullModeFlags;
+use malbi::engine::{
+    adapter::{list_adapters, DEVICE_INDEX_VAR},
+    app::{create_event_loop, Application, Backend},
+    game::{Game, InputMap, InputState, RenderContext},
+    material::{BlendMode, MaterialDesc, MaterialId, StencilDesc},
+};
+use winit::{dpi::LogicalSize, keyboard::KeyCode};
+
+/// Radians per second.
+const ROTATION_SPEED: f32 = 1.0;
+/// Size of the outline pass relative to the triangle.
+const OUTLINE_SCALE: f32 = 1.08;
+/// Stencil value marking the selected triangle's pixels.
+const SELECTED_STENCIL: u32 = 1;
+/// Size of the copies `--draws` adds, small to keep the GPU out of the way
+/// when measuring command buffer recording.
+const EXTRA_DRAW_SCALE: f32 = 0.02;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Action {
+    Pause,
+}
+
+/// The sample scene: a triangle spinning around its center, outlined as if
+/// selected when the device has a stencil attachment. Holding space pauses
+/// the rotation.
+struct RotatingTriangle {
+    material: Option<MaterialId>,
+    outline_material: Option<MaterialId>,
+    input_map: InputMap<Action>,
+    angle: f32,
+    aspect: f32,
+    paused: bool,
+    /// Tiny copies of the triangle drawn on top, for benchmarking draw
+    /// recording.
+    extra_draws: usize,
+}
+
+impl Default for RotatingTriangle {
+    fn default() -> Self {
+        let mut input_map = InputMap::new();
+        input_map.bind(Action::Pause, KeyCode::Space);
+        Self {
+            material: None,
+            outline_material: None,
+            input_map,
+            angle: 0.0,
+            aspect: 1.0,
+            paused: false,
+            extra_draws: 0,
+        }
+    }
+}
+
+impl Game for RotatingTriangle {
+    fn init(&mut self, ctx: &mut RenderContext) {
+        let outlined = ctx.stencil_supported();
+        let triangle = MaterialDesc {
+            vertex_shader: "shader/triangle.vert.spv".into(),
+            fragment_shader: "shader/triangle.frag.spv".into(),
+            blend_mode: BlendMode::Opaque,
+            cull_mode: CullModeFlags::BACK,
+            stencil: outlined.then(|| StencilDesc::write(SELECTED_STENCIL)),
+            ..Default::default()
+        };
+        // A larger copy of the triangle in a flat color, drawn where the
+        // triangle itself did not mark the stencil, leaves just a rim.
+        let outline = MaterialDesc {
+            fragment_shader: "shader/outline.frag.spv".into(),
+            stencil: Some(StencilDesc::not_equal(SELECTED_STENCIL)),
+            ..triangle.clone()
+        };
+        self.material = ctx
+            .register_material(triangle)
+            .inspect_err(|err| {
+                println!("Triangle shaders unavailable ({err}), run shader/compile.sh")
+            })
+            .ok();
+        self.outline_material = if outlined {
+            ctx.register_material(outline)
+                .inspect_err(|err| println!("Outline unavailable ({err})"))
+                .ok()
+        } else {
+            None
+        };
+    }
+
+    fn update(&mut self, dt: f32, input: &InputState) {
+        self.paused = input.is_action_pressed(&self.input_map, Action::Pause);
+        if !self.paused {
+            self.angle = (self.angle + ROTATION_SPEED * dt) % std::f32::consts::TAU;
+        }
+    }
+
+    fn render(&mut self, ctx: &mut RenderContext) {
+        let push_constants = |scale: f32| {
+            [self.angle, self.aspect, scale]
+                .iter()
+                .flat_map(|value| value.to_ne_bytes())
+                .collect::<Vec<_>>()
+        };
+        // The outline reads the stencil the triangle writes. Opaque draws are
+        // recorded by material in registration order, so registering the
+        // outline after the triangle is what draws it second.
+        if let Some(material) = self.material {
+            ctx.draw_with_push_constants(material, 3, &push_constants(1.0));
+            let extra_push_constants = push_constants(EXTRA_DRAW_SCALE);
+            for _ in 0..self.extra_draws {
+                ctx.draw_with_push_constants(material, 3, &extra_push_constants);
+            }
+        }
+        if let Some(outline_material) = self.outline_material {
+            ctx.draw_with_push_constants(outline_material, 3, &push_constants(OUTLINE_SCALE));
+        }
+    }
+
+    fn on_resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.aspect = width as f32 / height as f32;
+        }
+    }
+
+    fn wants_redraw(&self) -> bool {
+        !self.paused
+    }
+}
+
+/// Prints every Vulkan device, for picking one with `MALBI_DEVICE_INDEX`.
+fn print_adapters() {
+    match list_adapters() {
+        Ok(adapters) => {
+            for adapter in adapters {
+                println!(
+                    "{}: {} ({:?}, {} MiB)",
+                    adapter.index,
+                    adapter.name,
+                    adapter.device_type,
+                    adapter.vram / (1024 * 1024)
+                );
+                println!("   {:?}", adapter.capabilities);
+            }
+            println!("Set {DEVICE_INDEX_VAR} to an index to use that device");
+        }
+        Err(err) => {
+            println!("{err}");
+            std::process::exit(1);
+        }
+    }
+}
 
 fn main() {
-    let event_loop = EventLoop::builder()
-        .build()
-        .expect("Failed to create EventLoop");
-    let mut engine = Application::new(LogicalSize::new(1920, 1080));
+    let mut backend = Backend::Auto;
+    let mut strict_validation = false;
+    let mut unorm_swapchain = false;
+    let mut bench_frames = None;
+    let mut extra_draws = 0;
+    let mut parallel_recording = true;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--list-gpus" => {
+                print_adapters();
+                return;
+            }
+            "--x11" => backend = Backend::X11,
+            "--wayland" => backend = Backend::Wayland,
+            "--strict-validation" => strict_validation = true,
+            // The triangle's gradients should look the same either way.
+            "--unorm" => unorm_swapchain = true,
+            "--bench" => match args.next().map(|frames| frames.parse::<usize>()) {
+                Some(Ok(frames)) if frames > 0 => bench_frames = Some(frames),
+                _ => {
+                    println!("--bench expects a frame count greater than zero");
+                    std::process::exit(2);
+                }
+            },
+            // E.g. `--bench 1000 --draws 50000`, with and without
+            // `--single-threaded`, to compare the `record_ms` reported.
+            "--draws" => match args.next().map(|draws| draws.parse::<usize>()) {
+                Some(Ok(draws)) => extra_draws = draws,
+                _ => {
+                    println!("--draws expects a draw count");
+                    std::process::exit(2);
+                }
+            },
+            "--single-threaded" => parallel_recording = false,
+            _ => {
+                println!(
+                    "Unknown argument {arg:?}\nUsage: malbi [--list-gpus] [--x11 | --wayland] \
+                     [--strict-validation] [--unorm] [--bench FRAMES] [--draws COUNT] \
+                     [--single-threaded]"
+                );
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let event_loop = create_event_loop(backend).expect("Failed to create EventLoop");
+    let mut engine = Application::new(
+        LogicalSize::new(1920, 1080),
+        Box::new(RotatingTriangle {
+            extra_draws,
+            ..Default::default()
+        }),
+    )
+    .with_strict_validation(strict_validation)
+    .with_unorm_swapchain(unorm_swapchain);
+    if !parallel_recording {
+        engine = engine.with_parallel_recording_min_draws(None);
+    }
+    if let Some(frames) = bench_frames {
+        engine = engine.with_benchmark(frames);
+    }
     event_loop.run_app(&mut engine).unwrap();
-    drop(engine.base_config);
+    if engine.exit_error().is_some() {
+        println!("Exited (1)");
+        std::process::exit(1);
+    }
     println!("Exited (0)");
 }